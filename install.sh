@@ -40,8 +40,10 @@ echo "Installing to ~/.local/bin..."
 mkdir -p ~/.local/bin
 cp target/release/discord-overlay-daemon ~/.local/bin/
 cp target/release/chotop-config ~/.local/bin/
+cp target/release/chotopctl ~/.local/bin/
 chmod +x ~/.local/bin/discord-overlay-daemon
 chmod +x ~/.local/bin/chotop-config
+chmod +x ~/.local/bin/chotopctl
 
 # Install desktop entry for config GUI
 echo "Installing desktop entry..."
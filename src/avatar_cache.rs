@@ -4,6 +4,8 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 use tracing::{info, warn};
 
+use crate::discord_data::avatar_url;
+
 /// Cache for downloaded avatar images
 pub struct AvatarCache {
     cache_dir: PathBuf,
@@ -26,8 +28,8 @@ impl AvatarCache {
         }
     }
 
-    /// Get avatar path, downloading if necessary
-    pub async fn get_avatar(&self, user_id: &str, avatar_hash: &str) -> Option<PathBuf> {
+    /// Get avatar path, downloading if necessary. `guild_id` selects a per-server avatar
+    pub async fn get_avatar(&self, user_id: &str, guild_id: Option<&str>, avatar_hash: &str) -> Option<PathBuf> {
         let cache_key = format!("{}_{}", user_id, avatar_hash);
 
         // Check if already cached
@@ -41,14 +43,7 @@ impl AvatarCache {
         }
 
         // Download avatar
-        let url = if avatar_hash.starts_with("http") {
-            avatar_hash.to_string()
-        } else {
-            format!(
-                "https://cdn.discordapp.com/avatars/{}/{}.png?size=64",
-                user_id, avatar_hash
-            )
-        };
+        let url = avatar_url(user_id, guild_id, avatar_hash);
 
         let path = self.cache_dir.join(format!("{}.png", cache_key));
 
@@ -0,0 +1,190 @@
+use gtk4::gdk;
+use gtk4::glib;
+use gtk4::prelude::*;
+use gtk4::{Align, ApplicationWindow, Box as GtkBox, Label, Orientation};
+use gtk4_layer_shell::{KeyboardMode, Layer, LayerShell};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::config::{Config, Position};
+use crate::discord_data::ChatMessage;
+use crate::overlay_window::anchor_window;
+
+/// Length of the fade-out transition, see `.chat-window` in the CSS
+const FADE_DURATION: Duration = Duration::from_millis(600);
+
+/// Layer-shell window showing the tail of the voice channel's text chat
+pub struct ChatWindow {
+    window: ApplicationWindow,
+    messages_box: GtkBox,
+    rows: VecDeque<GtkBox>,
+    /// `chat_enabled`; a disabled chat drops every message
+    enabled: bool,
+    /// Channel the shown messages belong to
+    channel_id: Option<String>,
+    max_messages: usize,
+    /// `None` keeps the chat visible
+    fade_after: Option<Duration>,
+    /// Bumped on every message so older fade timers know they are stale
+    generation: Rc<Cell<u64>>,
+}
+
+impl ChatWindow {
+    pub fn new(app: &gtk4::Application, config: &Config) -> Self {
+        let window = ApplicationWindow::builder()
+            .application(app)
+            .title("Discord Chat")
+            .decorated(false)
+            .resizable(false)
+            .build();
+
+        window.init_layer_shell();
+        window.set_layer(Layer::Overlay);
+        window.set_keyboard_mode(KeyboardMode::None);
+        anchor_window(&window, config.chat_position, config.chat_margin);
+        window.set_namespace("discord-overlay-chat");
+
+        // The chat is read-only, so clicks always go through
+        window.connect_realize(|window| {
+            if let Some(surface) = window.surface() {
+                surface.set_input_region(&gdk::cairo::Region::create());
+            }
+        });
+
+        let messages_box = GtkBox::new(Orientation::Vertical, 2);
+        messages_box.add_css_class("chat-window");
+        messages_box.set_valign(Self::valign(config.chat_position));
+        window.set_child(Some(&messages_box));
+
+        // Hidden until the first message
+        window.set_visible(false);
+
+        Self {
+            window,
+            messages_box,
+            rows: VecDeque::new(),
+            enabled: config.chat_enabled,
+            channel_id: None,
+            max_messages: config.chat_max_messages.max(1),
+            fade_after: (config.chat_fade_secs > 0).then(|| Duration::from_secs(config.chat_fade_secs)),
+            generation: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// Keep the newest message next to the screen edge the window is anchored to
+    fn valign(position: Position) -> Align {
+        match position {
+            Position::TopLeft | Position::TopRight => Align::Start,
+            Position::BottomLeft | Position::BottomRight => Align::End,
+        }
+    }
+
+    /// Take the `chat_*` settings of a new config, keeping the messages shown
+    pub fn apply_config(&mut self, config: &Config) {
+        self.enabled = config.chat_enabled;
+        if !self.enabled {
+            self.clear();
+        }
+
+        anchor_window(&self.window, config.chat_position, config.chat_margin);
+        self.messages_box.set_valign(Self::valign(config.chat_position));
+
+        self.max_messages = config.chat_max_messages.max(1);
+        self.trim();
+
+        let fade_after = (config.chat_fade_secs > 0).then(|| Duration::from_secs(config.chat_fade_secs));
+        if fade_after != self.fade_after {
+            self.fade_after = fade_after;
+            if !self.rows.is_empty() {
+                self.schedule_fade();
+            }
+        }
+    }
+
+    /// Channel the shown messages belong to
+    pub fn channel_id(&self) -> Option<&str> {
+        self.channel_id.as_deref()
+    }
+
+    /// Append a message, dropping the oldest ones beyond `chat_max_messages`
+    pub fn show_message(&mut self, message: ChatMessage) {
+        if !self.enabled {
+            return;
+        }
+        if self.channel_id.as_deref() != Some(message.channel_id.as_str()) {
+            self.clear();
+            self.channel_id = Some(message.channel_id.clone());
+        }
+
+        let row = GtkBox::new(Orientation::Horizontal, 6);
+        row.add_css_class("chat-message");
+
+        let author = Label::new(Some(&message.author_name));
+        author.add_css_class("chat-author");
+        author.set_valign(Align::Start);
+        row.append(&author);
+
+        let content = Label::new(Some(&message.content));
+        content.add_css_class("chat-content");
+        content.set_wrap(true);
+        content.set_wrap_mode(gtk4::pango::WrapMode::WordChar);
+        content.set_max_width_chars(40);
+        content.set_xalign(0.0);
+        content.set_hexpand(true);
+        row.append(&content);
+
+        self.messages_box.append(&row);
+        self.rows.push_back(row);
+        self.trim();
+
+        self.messages_box.remove_css_class("faded");
+        self.window.set_visible(true);
+        self.schedule_fade();
+    }
+
+    fn trim(&mut self) {
+        while self.rows.len() > self.max_messages {
+            if let Some(oldest) = self.rows.pop_front() {
+                self.messages_box.remove(&oldest);
+            }
+        }
+    }
+
+    /// Drop every message, e.g. when the shown voice channel changes
+    pub fn clear(&mut self) {
+        for row in self.rows.drain(..) {
+            self.messages_box.remove(&row);
+        }
+        self.channel_id = None;
+        self.generation.set(self.generation.get() + 1);
+        self.window.set_visible(false);
+    }
+
+    fn schedule_fade(&self) {
+        let generation = self.generation.get() + 1;
+        self.generation.set(generation);
+
+        let Some(fade_after) = self.fade_after else {
+            return;
+        };
+
+        let current = self.generation.clone();
+        let messages_box = self.messages_box.clone();
+        let window = self.window.clone();
+        glib::timeout_add_local_once(fade_after, move || {
+            if current.get() != generation {
+                return;
+            }
+            messages_box.add_css_class("faded");
+
+            // Unmap once the transition is over
+            glib::timeout_add_local_once(FADE_DURATION, move || {
+                if current.get() == generation {
+                    window.set_visible(false);
+                }
+            });
+        });
+    }
+}
@@ -0,0 +1,111 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use tracing::warn;
+
+use crate::config::Config;
+
+pub const USAGE: &str = "\
+Usage: discord-overlay-daemon [OPTIONS]
+
+Options:
+  --port <PORT>     WebSocket port (overrides `port` in config.toml)
+  --bind <ADDRESS>  WebSocket bind address (overrides `bind_address`)
+  --record <FILE>   Append every incoming plugin frame to FILE (JSONL)
+  --replay <FILE>   Replay a recording instead of listening for plugins
+  --replay-speed <FACTOR>
+                    Replay speed: 1 real time (default), 10 ten times faster, 0 no delay
+  -h, --help        Print this help";
+
+/// Command-line overrides for the daemon
+#[derive(Debug, Clone, Default)]
+pub struct CliArgs {
+    pub port: Option<u16>,
+    pub bind: Option<IpAddr>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub replay_speed: Option<f64>,
+    pub help: bool,
+}
+
+impl CliArgs {
+    pub fn parse() -> Result<Self, String> {
+        let mut cli = Self::default();
+        let mut args = std::env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--port" => {
+                    let value = args.next().ok_or("--port needs a value")?;
+                    let port: u16 = value
+                        .parse()
+                        .map_err(|_| format!("invalid port: {}", value))?;
+                    if port == 0 {
+                        return Err("port must be between 1 and 65535".to_string());
+                    }
+                    cli.port = Some(port);
+                }
+                "--bind" => {
+                    let value = args.next().ok_or("--bind needs a value")?;
+                    let ip = value
+                        .parse()
+                        .map_err(|_| format!("invalid bind address: {}", value))?;
+                    cli.bind = Some(ip);
+                }
+                "--record" => {
+                    let value = args.next().ok_or("--record needs a file")?;
+                    cli.record = Some(PathBuf::from(value));
+                }
+                "--replay" => {
+                    let value = args.next().ok_or("--replay needs a file")?;
+                    cli.replay = Some(PathBuf::from(value));
+                }
+                "--replay-speed" => {
+                    let value = args.next().ok_or("--replay-speed needs a value")?;
+                    let speed: f64 = value
+                        .parse()
+                        .ok()
+                        .filter(|speed: &f64| speed.is_finite() && *speed >= 0.0)
+                        .ok_or_else(|| format!("invalid replay speed: {}", value))?;
+                    cli.replay_speed = Some(speed);
+                }
+                "-h" | "--help" => cli.help = true,
+                other => return Err(format!("unknown argument: {}", other)),
+            }
+        }
+
+        if cli.replay_speed.is_some() && cli.replay.is_none() {
+            return Err("--replay-speed needs --replay".to_string());
+        }
+
+        Ok(cli)
+    }
+
+    /// Recording to replay and its speed
+    pub fn replay(&self) -> Option<(PathBuf, f64)> {
+        self.replay.clone().map(|path| (path, self.replay_speed.unwrap_or(1.0)))
+    }
+
+    /// WebSocket listen address: CLI flags win over config, invalid config values fall back to defaults
+    pub fn listen_addr(&self, config: &Config) -> SocketAddr {
+        let port = match self.port.unwrap_or(config.port) {
+            0 => {
+                warn!("Invalid port 0 in config, using {}", Config::default().port);
+                Config::default().port
+            }
+            port => port,
+        };
+
+        let ip = self.bind.unwrap_or_else(|| {
+            config.bind_address.parse().unwrap_or_else(|_| {
+                warn!("Invalid bind_address {:?} in config, using 127.0.0.1", config.bind_address);
+                IpAddr::V4(Ipv4Addr::LOCALHOST)
+            })
+        });
+
+        if !ip.is_loopback() {
+            warn!("WebSocket server bound to non-loopback address {}, other machines may reach it", ip);
+        }
+
+        SocketAddr::new(ip, port)
+    }
+}
@@ -1,7 +1,8 @@
 use serde::{Deserialize, Serialize};
 use std::fs;
+use std::net::IpAddr;
 use std::path::PathBuf;
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 
 /// Overlay position on screen
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
@@ -13,6 +14,46 @@ pub enum Position {
     BottomLeft,
 }
 
+/// Who decides the overlay layout when the plugin sends its own settings
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub enum SettingsSource {
+    /// `config.toml` wins, the plugin's layout settings are ignored
+    #[default]
+    Local,
+    /// Alignment and transparency from the plugin's settings are applied as they arrive
+    Plugin,
+}
+
+/// Which plugin session the overlay shows when several Discord clients are connected
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub enum SessionMode {
+    /// Show the client that most recently joined a voice channel
+    #[default]
+    LastJoined,
+    /// Show only the client logged in as `pinned_session`
+    Pinned,
+    /// Show the users of every client in one list
+    Merged,
+}
+
+/// Where voice state comes from
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum EventSourceKind {
+    /// OrbolayBridge plugin (Equicord/Equibop) over WebSocket
+    Orbolay,
+    /// Official Discord client's local RPC socket
+    DiscordRpc,
+}
+
+/// How plugins can reach the daemon; both speak the same WebSocket protocol
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum PluginTransport {
+    /// TCP on `bind_address`:`port`
+    Tcp,
+    /// Unix socket in `$XDG_RUNTIME_DIR`, only reachable by the current user
+    Unix,
+}
+
 /// Configuration for the overlay
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -24,17 +65,177 @@ pub struct Config {
     pub opacity: f64,
     /// WebSocket port
     pub port: u16,
+    /// Address the WebSocket server binds to
+    #[serde(default = "default_bind_address")]
+    pub bind_address: String,
+    /// How many ports after `port` to try when it is already taken (0 disables the fallback)
+    #[serde(default = "default_port_fallback_range")]
+    pub port_fallback_range: u16,
+    /// Event sources to run
+    #[serde(default = "default_event_sources")]
+    pub event_sources: Vec<EventSourceKind>,
+    /// Discord RPC socket to use instead of searching for `discord-ipc-N`
+    #[serde(default)]
+    pub discord_rpc_socket: Option<String>,
+    /// OAuth client id used to authorize with the Discord client
+    #[serde(default = "default_discord_rpc_client_id")]
+    pub discord_rpc_client_id: String,
+    /// Endpoint exchanging the authorization code for an access token
+    #[serde(default = "default_discord_rpc_token_url")]
+    pub discord_rpc_token_url: String,
+    /// Transports the plugin server listens on
+    #[serde(default = "default_plugin_transports")]
+    pub plugin_transports: Vec<PluginTransport>,
     /// Avatar size in pixels
     pub avatar_size: i32,
     /// Enable click-through (mouse events pass through overlay)
     #[serde(default = "default_click_through")]
     pub click_through: bool,
+    /// Whether the plugin's alignment/transparency settings override this file
+    #[serde(default)]
+    pub settings_source: SettingsSource,
+    /// Color usernames by their role, for plugins that send `roleColor`
+    #[serde(default)]
+    pub role_colors: bool,
+    /// Show the voice channel's text chat in its own window
+    #[serde(default = "default_chat_enabled")]
+    pub chat_enabled: bool,
+    /// Chat window position on screen
+    #[serde(default = "default_chat_position")]
+    pub chat_position: Position,
+    /// Chat window distance from the screen edge in pixels
+    #[serde(default = "default_chat_margin")]
+    pub chat_margin: i32,
+    /// Chat window background opacity (0.0 to 1.0)
+    #[serde(default = "default_chat_opacity")]
+    pub chat_opacity: f64,
+    /// Messages kept in the chat tail
+    #[serde(default = "default_chat_max_messages")]
+    pub chat_max_messages: usize,
+    /// Seconds without new messages before the chat fades out (0 keeps it visible)
+    #[serde(default = "default_chat_fade_secs")]
+    pub chat_fade_secs: u64,
+    /// How multiple connected Discord clients are shown
+    #[serde(default)]
+    pub session_mode: SessionMode,
+    /// Discord user id shown when `session_mode` is `Pinned`
+    #[serde(default)]
+    pub pinned_session: Option<String>,
+    /// Browser origins allowed to open a plugin connection (clients sending no Origin are always allowed)
+    #[serde(default = "default_allowed_origins")]
+    pub allowed_origins: Vec<String>,
+    /// Largest WebSocket message accepted from a plugin (bytes)
+    #[serde(default = "default_max_message_size")]
+    pub max_message_size: usize,
+    /// Largest single WebSocket frame accepted from a plugin (bytes)
+    #[serde(default = "default_max_frame_size")]
+    pub max_frame_size: usize,
+    /// Sustained messages per second a single connection may send before it is closed
+    #[serde(default = "default_max_messages_per_second")]
+    pub max_messages_per_second: u32,
+    /// Seconds between WebSocket pings to the plugin
+    #[serde(default = "default_heartbeat_interval_secs")]
+    pub heartbeat_interval_secs: u64,
+    /// Seconds without any traffic before the plugin counts as lost
+    #[serde(default = "default_heartbeat_grace_secs")]
+    pub heartbeat_grace_secs: u64,
+    /// Seconds a lost plugin's roster stays dimmed before it is cleared
+    #[serde(default = "default_source_lost_clear_secs")]
+    pub source_lost_clear_secs: u64,
+    /// Close plugin connections that don't present the pairing token in their first frame
+    #[serde(default = "default_require_pairing")]
+    pub require_pairing: bool,
 }
 
 fn default_click_through() -> bool {
     true
 }
 
+fn default_chat_enabled() -> bool {
+    true
+}
+
+fn default_chat_position() -> Position {
+    Position::BottomLeft
+}
+
+fn default_chat_margin() -> i32 {
+    20
+}
+
+fn default_chat_opacity() -> f64 {
+    0.8
+}
+
+fn default_chat_max_messages() -> usize {
+    8
+}
+
+fn default_chat_fade_secs() -> u64 {
+    30
+}
+
+fn default_bind_address() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_port_fallback_range() -> u16 {
+    10
+}
+
+fn default_event_sources() -> Vec<EventSourceKind> {
+    vec![EventSourceKind::Orbolay]
+}
+
+/// Discord's StreamKit overlay client, which needs no client secret
+fn default_discord_rpc_client_id() -> String {
+    "207646673902501888".to_string()
+}
+
+fn default_discord_rpc_token_url() -> String {
+    "https://streamkit.discord.com/overlay/token".to_string()
+}
+
+fn default_plugin_transports() -> Vec<PluginTransport> {
+    vec![PluginTransport::Tcp]
+}
+
+fn default_require_pairing() -> bool {
+    true
+}
+
+fn default_allowed_origins() -> Vec<String> {
+    vec![
+        "https://discord.com".to_string(),
+        "https://ptb.discord.com".to_string(),
+        "https://canary.discord.com".to_string(),
+    ]
+}
+
+fn default_max_message_size() -> usize {
+    1024 * 1024
+}
+
+fn default_max_frame_size() -> usize {
+    512 * 1024
+}
+
+fn default_heartbeat_interval_secs() -> u64 {
+    10
+}
+
+fn default_heartbeat_grace_secs() -> u64 {
+    30
+}
+
+fn default_source_lost_clear_secs() -> u64 {
+    120
+}
+
+fn default_max_messages_per_second() -> u32 {
+    100
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -42,8 +243,33 @@ impl Default for Config {
             margin: 20,
             opacity: 0.9,
             port: 6888,
+            bind_address: default_bind_address(),
+            port_fallback_range: default_port_fallback_range(),
+            event_sources: default_event_sources(),
+            discord_rpc_socket: None,
+            discord_rpc_client_id: default_discord_rpc_client_id(),
+            discord_rpc_token_url: default_discord_rpc_token_url(),
+            plugin_transports: default_plugin_transports(),
             avatar_size: 32,
             click_through: true,
+            role_colors: false,
+            settings_source: SettingsSource::Local,
+            chat_enabled: default_chat_enabled(),
+            chat_position: default_chat_position(),
+            chat_margin: default_chat_margin(),
+            chat_opacity: default_chat_opacity(),
+            chat_max_messages: default_chat_max_messages(),
+            chat_fade_secs: default_chat_fade_secs(),
+            session_mode: SessionMode::LastJoined,
+            pinned_session: None,
+            allowed_origins: default_allowed_origins(),
+            max_message_size: default_max_message_size(),
+            max_frame_size: default_max_frame_size(),
+            max_messages_per_second: default_max_messages_per_second(),
+            heartbeat_interval_secs: default_heartbeat_interval_secs(),
+            heartbeat_grace_secs: default_heartbeat_grace_secs(),
+            source_lost_clear_secs: default_source_lost_clear_secs(),
+            require_pairing: true,
         }
     }
 }
@@ -64,23 +290,89 @@ impl Config {
         config_dir.join("discord-overlay").join("config.toml")
     }
 
+    /// Directory for sockets and runtime state
+    pub fn runtime_dir() -> PathBuf {
+        std::env::var("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from("/tmp"))
+    }
+
+    /// File the daemon writes the WebSocket port it actually bound to
+    pub fn port_file_path() -> PathBuf {
+        Self::runtime_dir().join("chotop-websocket-port")
+    }
+
+    /// Port the running daemon listens on, falling back to the configured one
+    pub fn active_port(&self) -> u16 {
+        fs::read_to_string(Self::port_file_path())
+            .ok()
+            .and_then(|content| content.trim().parse().ok())
+            .unwrap_or(self.port)
+    }
+
+    /// Reset plugin limits set to 0 (every connection would be closed, or the heartbeat
+    /// timer would panic) to their defaults. Returns the keys that were reset.
+    pub fn clamp_limits(&mut self) -> Vec<&'static str> {
+        let mut reset = Vec::new();
+        if self.max_message_size == 0 {
+            self.max_message_size = default_max_message_size();
+            reset.push("max_message_size");
+        }
+        if self.max_frame_size == 0 {
+            self.max_frame_size = default_max_frame_size();
+            reset.push("max_frame_size");
+        }
+        if self.max_messages_per_second == 0 {
+            self.max_messages_per_second = default_max_messages_per_second();
+            reset.push("max_messages_per_second");
+        }
+        if self.heartbeat_interval_secs == 0 {
+            self.heartbeat_interval_secs = default_heartbeat_interval_secs();
+            reset.push("heartbeat_interval_secs");
+        }
+        if self.heartbeat_grace_secs == 0 {
+            self.heartbeat_grace_secs = default_heartbeat_grace_secs();
+            reset.push("heartbeat_grace_secs");
+        }
+        reset
+    }
+
+    /// Reset a `bind_address` that is not an IP address to the default.
+    /// Returns whether it was reset.
+    pub fn clamp_bind_address(&mut self) -> bool {
+        if self.bind_address.parse::<IpAddr>().is_ok() {
+            return false;
+        }
+        self.bind_address = default_bind_address();
+        true
+    }
+
+    /// Read config.toml as it is, without creating, fixing or saving it
+    pub fn try_load() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let path = Self::config_path();
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        toml::from_str(&content).map_err(|e| format!("Failed to parse {}: {}", path.display(), e).into())
+    }
+
     /// Load config from file or create default
     pub fn load() -> Self {
         let path = Self::config_path();
 
         if path.exists() {
-            match fs::read_to_string(&path) {
-                Ok(content) => match toml::from_str(&content) {
-                    Ok(config) => {
-                        info!("Config loaded from {:?}", path);
-                        return config;
+            match Self::try_load() {
+                Ok(mut config) => {
+                    info!("Config loaded from {:?}", path);
+                    for key in config.clamp_limits() {
+                        error!("{} in {:?} must be greater than 0, using the default", key, path);
                     }
-                    Err(e) => {
-                        warn!("Failed to parse config: {}", e);
+                    if config.clamp_bind_address() {
+                        error!("bind_address in {:?} is not an IP address, using {}", path, config.bind_address);
                     }
-                },
+                    return config;
+                }
                 Err(e) => {
-                    warn!("Failed to read config file: {}", e);
+                    warn!("{}", e);
                 }
             }
         }
@@ -113,3 +405,34 @@ impl Config {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_limits_fall_back_to_defaults() {
+        let mut config = Config {
+            max_frame_size: 0,
+            max_messages_per_second: 0,
+            ..Config::default()
+        };
+        assert_eq!(config.clamp_limits(), ["max_frame_size", "max_messages_per_second"]);
+        assert_eq!(config.max_messages_per_second, default_max_messages_per_second());
+        assert_eq!(config.max_frame_size, default_max_frame_size());
+        assert!(config.clamp_limits().is_empty());
+    }
+
+    #[test]
+    fn bind_address_must_be_an_ip() {
+        let mut config = Config {
+            bind_address: "localhost".to_string(),
+            ..Config::default()
+        };
+        assert!(config.clamp_bind_address());
+        assert_eq!(config.bind_address, default_bind_address());
+
+        config.bind_address = "::1".to_string();
+        assert!(!config.clamp_bind_address());
+    }
+}
@@ -1,40 +1,92 @@
 mod config;
+// Only the protocol types are used here, the async client is for chotopctl
+#[allow(dead_code)]
+mod control_ipc;
+mod pairing;
 
 use config::{Config, Position};
+use control_ipc::{ControlCommand, ControlResponse, DaemonStatus};
+use pairing::PairingToken;
 use gtk4::prelude::*;
 use gtk4::{
     Adjustment, Application, ApplicationWindow, Box as GtkBox, Button,
     ComboBoxText, Label, Orientation, Scale, SpinButton, Switch,
 };
-use std::process::Command;
+use std::time::Duration;
 
 const APP_ID: &str = "com.discord.overlay.config";
 
-fn is_daemon_running() -> bool {
-    Command::new("pgrep")
-        .arg("-f")
-        .arg("discord-overlay-daemon")
-        .output()
-        .map(|output| output.status.success())
-        .unwrap_or(false)
+/// How long a status query may block the GTK thread
+const STATUS_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// How long Save waits for the daemon, which may restart its plugin server for the new config
+const UPDATE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Send one request over the control socket and read the reply.
+/// Blocking on purpose: this runs on the GTK thread and must give up after `timeout`.
+fn control_request(command: &ControlCommand, timeout: Duration) -> std::io::Result<ControlResponse> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    let mut stream = UnixStream::connect(control_ipc::socket_path())?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let mut request = serde_json::to_vec(command)?;
+    request.push(b'\n');
+    stream.write_all(&request)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Ask the daemon for its status over the control socket, `None` if it doesn't answer
+fn query_daemon_status() -> Option<DaemonStatus> {
+    match control_request(&ControlCommand::GetStatus, STATUS_TIMEOUT).ok()? {
+        ControlResponse::Ok { data } => serde_json::from_value(data?).ok(),
+        ControlResponse::Error { .. } => None,
+    }
+}
+
+/// Hand a saved config to the running daemon, describing the outcome for the Save dialog
+fn apply_to_daemon(config: &Config) -> String {
+    use std::io::ErrorKind;
+
+    match control_request(&ControlCommand::UpdateConfig(Box::new(config.clone())), UPDATE_TIMEOUT) {
+        Ok(ControlResponse::Ok { .. }) => "The running daemon now uses the new settings.".to_string(),
+        Ok(ControlResponse::Error { message }) => {
+            format!("The daemon kept its previous settings: {}", message)
+        }
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+            "The daemon is not running; it will use these settings when it starts.".to_string()
+        }
+        Err(e) => format!(
+            "The daemon did not confirm the new settings ({}). Run `chotopctl reload` to apply them.",
+            e
+        ),
+    }
 }
 
-fn is_websocket_active() -> bool {
-    use std::net::TcpStream;
-    use std::time::Duration;
+fn is_websocket_active(config: &Config, port: u16) -> bool {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
+
+    // A wildcard bind is reachable through loopback
+    let ip = config
+        .bind_address
+        .parse::<IpAddr>()
+        .ok()
+        .filter(|ip| !ip.is_unspecified())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
 
     TcpStream::connect_timeout(
-        &"127.0.0.1:6888".parse().unwrap(),
+        &SocketAddr::new(ip, port),
         Duration::from_millis(100)
     ).is_ok()
 }
 
-fn is_ipc_active() -> bool {
-    use std::path::Path;
-
-    let socket_path = format!("/run/user/{}/chotop-control.sock",
-        std::env::var("UID").unwrap_or_else(|_| "1000".to_string()));
-    Path::new(&socket_path).exists()
+/// Token for display, or why there is none
+fn token_text(token: std::io::Result<String>) -> String {
+    token.unwrap_or_else(|e| format!("Unavailable: {}", e))
 }
 
 fn main() -> gtk4::glib::ExitCode {
@@ -116,25 +168,33 @@ fn build_ui(app: &Application) {
         let ipc_label = ipc_label.clone();
         move || {
             // Daemon status
-            if is_daemon_running() {
+            let daemon_status = query_daemon_status();
+            if let Some(status) = &daemon_status {
                 status_indicator.set_markup("<span foreground='#4ade80' size='x-large'>●</span>");
-                status_label.set_text("Daemon is running");
+                status_label.set_text(&format!(
+                    "Daemon is running (v{}, up {} min)",
+                    status.version,
+                    status.uptime_secs / 60,
+                ));
             } else {
                 status_indicator.set_markup("<span foreground='#ef4444' size='x-large'>●</span>");
                 status_label.set_text("Daemon is stopped");
             }
 
-            // WebSocket status
-            if is_websocket_active() {
+            // WebSocket status (the daemon may have fallen back to another port).
+            // Only read: the file may be half edited, and must not be replaced by defaults
+            let config = Config::try_load().unwrap_or_default();
+            let port = config.active_port();
+            if is_websocket_active(&config, port) {
                 ws_indicator.set_markup("<span foreground='#4ade80' size='large'>●</span>");
-                ws_label.set_text("WebSocket: Connected (port 6888)");
+                ws_label.set_text(&format!("WebSocket: Connected (port {})", port));
             } else {
                 ws_indicator.set_markup("<span foreground='#ef4444' size='large'>●</span>");
                 ws_label.set_text("WebSocket: Disconnected");
             }
 
             // IPC status
-            if is_ipc_active() {
+            if daemon_status.is_some() {
                 ipc_indicator.set_markup("<span foreground='#4ade80' size='large'>●</span>");
                 ipc_label.set_text("IPC: Active");
             } else {
@@ -234,9 +294,50 @@ fn build_ui(app: &Application) {
 
     main_box.append(&appearance_section);
 
+    // Pairing Section
+    let pairing_section = GtkBox::new(Orientation::Vertical, 12);
+    let pairing_header = Label::new(Some("Pairing"));
+    pairing_header.add_css_class("title-2");
+    pairing_header.set_xalign(0.0);
+    pairing_section.append(&pairing_header);
+
+    // Require pairing
+    let require_pairing_box = GtkBox::new(Orientation::Horizontal, 12);
+    let require_pairing_label = Label::new(Some("Require Pairing:"));
+    require_pairing_label.set_width_chars(18);
+    require_pairing_label.set_xalign(0.0);
+    let require_pairing_switch = Switch::new();
+    require_pairing_switch.set_active(config.require_pairing);
+    require_pairing_switch.set_halign(gtk4::Align::Start);
+    require_pairing_box.append(&require_pairing_label);
+    require_pairing_box.append(&require_pairing_switch);
+    pairing_section.append(&require_pairing_box);
+
+    // Token (selectable so it can be copied into the plugin settings)
+    let token_box = GtkBox::new(Orientation::Horizontal, 12);
+    let token_label = Label::new(Some("Pairing Token:"));
+    token_label.set_width_chars(18);
+    token_label.set_xalign(0.0);
+    let token_value = Label::new(Some(&token_text(PairingToken::load_or_create())));
+    token_value.set_selectable(true);
+    token_value.set_hexpand(true);
+    token_value.set_xalign(0.0);
+    token_value.add_css_class("monospace");
+    let regenerate_btn = Button::with_label("Regenerate");
+    let token_value_clone = token_value.clone();
+    regenerate_btn.connect_clicked(move |_| {
+        token_value_clone.set_text(&token_text(PairingToken::regenerate()));
+    });
+    token_box.append(&token_label);
+    token_box.append(&token_value);
+    token_box.append(&regenerate_btn);
+    pairing_section.append(&token_box);
+
+    main_box.append(&pairing_section);
+
     // Info Section
     let info_box = GtkBox::new(Orientation::Vertical, 8);
-    let info_label = Label::new(Some("💡 Tip: Restart the daemon after changing settings"));
+    let info_label = Label::new(Some("💡 Tip: Save also applies the settings to the running daemon; after editing config.toml by hand, run `chotopctl reload`"));
     info_label.add_css_class("dim-label");
     info_label.set_wrap(true);
     info_box.append(&info_label);
@@ -306,11 +407,13 @@ fn build_ui(app: &Application) {
             margin: margin_spin.value() as i32,
             opacity: opacity_scale.value(),
             avatar_size: avatar_spin.value() as i32,
-            port: config.port,
             click_through: click_through_switch.is_active(),
+            require_pairing: require_pairing_switch.is_active(),
+            ..config.clone()
         };
 
         new_config.save();
+        let outcome = apply_to_daemon(&new_config);
 
         // Show success dialog
         let dialog = gtk4::MessageDialog::builder()
@@ -318,7 +421,7 @@ fn build_ui(app: &Application) {
             .modal(true)
             .buttons(gtk4::ButtonsType::Ok)
             .text("Configuration Saved")
-            .secondary_text("Please restart the daemon for changes to take effect.")
+            .secondary_text(outcome.as_str())
             .build();
 
         dialog.connect_response(move |dialog, _| {
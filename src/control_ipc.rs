@@ -1,115 +1,101 @@
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{UnixListener, UnixStream};
-use tracing::{error, info};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
 
+/// Control socket of the running daemon
+pub fn socket_path() -> PathBuf {
+    crate::config::Config::runtime_dir().join("chotop-control.sock")
+}
+
+/// Requests on the control socket, one JSON value per line
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ControlCommand {
     EnableTestMode,
     DisableTestMode,
-    UpdateConfig(crate::config::Config),
-    Restart,
+    UpdateConfig(Box<crate::config::Config>),
+    /// Voice controls forwarded to the connected plugin
+    ToggleMute,
+    ToggleDeafen,
+    DisconnectVoice,
+    MoveToChannel(String),
+    /// Query plugin protocol counters
+    GetProtocolStats,
+    /// Query the status of every event source
+    GetSourceStatus,
+    /// Query the daemon version, pid and uptime
+    GetStatus,
+    /// Query the config the daemon is running with
+    GetConfig,
+    /// Query the users and channel shown by the overlay
+    GetRoster,
+    /// Query every connected plugin session
+    GetSessions,
+    /// Show, hide or toggle the voice overlay window
+    ShowOverlay,
+    HideOverlay,
+    ToggleOverlay,
     Quit,
 }
 
-pub struct ControlIpcServer {
-    socket_path: PathBuf,
-}
-
-impl ControlIpcServer {
-    pub fn new() -> Self {
-        let socket_path = Self::get_socket_path();
-
-        // Remove old socket if it exists
-        if socket_path.exists() {
-            let _ = std::fs::remove_file(&socket_path);
-        }
-
-        Self { socket_path }
-    }
-
-    pub fn get_socket_path() -> PathBuf {
-        let runtime_dir = std::env::var("XDG_RUNTIME_DIR")
-            .unwrap_or_else(|_| "/tmp".to_string());
-        PathBuf::from(runtime_dir).join("chotop-control.sock")
-    }
-
-    pub async fn run(
-        &self,
-        mut command_rx: tokio::sync::mpsc::Receiver<ControlCommand>,
-    ) -> std::io::Result<()> {
-        let listener = UnixListener::bind(&self.socket_path)?;
-        info!("Control IPC server listening on {:?}", self.socket_path);
-
-        loop {
-            tokio::select! {
-                Ok((stream, _)) = listener.accept() => {
-                    self.handle_connection(stream).await;
-                }
-                Some(cmd) = command_rx.recv() => {
-                    // Handle internal commands if needed
-                    info!("Received internal command: {:?}", cmd);
-                }
-            }
-        }
-    }
-
-    async fn handle_connection(&self, mut stream: UnixStream) {
-        let mut buffer = vec![0u8; 4096];
-
-        match stream.read(&mut buffer).await {
-            Ok(n) if n > 0 => {
-                match serde_json::from_slice::<ControlCommand>(&buffer[..n]) {
-                    Ok(command) => {
-                        info!("Received command: {:?}", command);
-
-                        // Send acknowledgment
-                        let response = b"OK";
-                        let _ = stream.write_all(response).await;
-                    }
-                    Err(e) => {
-                        error!("Failed to deserialize command: {}", e);
-                    }
-                }
-            }
-            Ok(_) => {
-                info!("Empty message received");
-            }
-            Err(e) => {
-                error!("Failed to read from socket: {}", e);
-            }
-        }
-    }
+/// Reply to a control request, one JSON value per line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum ControlResponse {
+    Ok {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        data: Option<serde_json::Value>,
+    },
+    Error {
+        message: String,
+    },
 }
 
-impl Drop for ControlIpcServer {
-    fn drop(&mut self) {
-        let _ = std::fs::remove_file(&self.socket_path);
-    }
+/// Daemon version and uptime, answer to `GetStatus`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DaemonStatus {
+    pub version: String,
+    pub pid: u32,
+    pub uptime_secs: u64,
 }
 
 pub struct ControlIpcClient;
 
 impl ControlIpcClient {
-    pub async fn send_command(command: ControlCommand) -> std::io::Result<()> {
-        let socket_path = ControlIpcServer::get_socket_path();
-
-        let mut stream = UnixStream::connect(&socket_path).await?;
+    /// Send one request and wait for the daemon's reply
+    pub async fn request(command: &ControlCommand) -> std::io::Result<ControlResponse> {
+        let stream = UnixStream::connect(socket_path()).await?;
+        let (reader, mut writer) = stream.into_split();
 
-        let data = serde_json::to_vec(&command)
+        let mut data = serde_json::to_vec(command)
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        data.push(b'\n');
+        writer.write_all(&data).await?;
+
+        let mut line = String::new();
+        BufReader::new(reader).read_line(&mut line).await?;
+        if line.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "daemon closed the connection without replying",
+            ));
+        }
 
-        stream.write_all(&data).await?;
+        serde_json::from_str(&line)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
 
-        // Wait for acknowledgment
-        let mut response = vec![0u8; 1024];
-        let n = stream.read(&mut response).await?;
+    /// Send a command, failing if the daemon answers with an error
+    pub async fn send_command(command: ControlCommand) -> std::io::Result<()> {
+        Self::query(command).await.map(|_| ())
+    }
 
-        if &response[..n] == b"OK" {
-            info!("Command sent successfully");
+    /// Send a query command and return the data of the daemon's reply
+    pub async fn query(command: ControlCommand) -> std::io::Result<serde_json::Value> {
+        match Self::request(&command).await? {
+            ControlResponse::Ok { data } => Ok(data.unwrap_or_default()),
+            ControlResponse::Error { message } => Err(std::io::Error::other(message)),
         }
-
-        Ok(())
     }
 }
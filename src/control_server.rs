@@ -0,0 +1,190 @@
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, info, warn};
+
+use crate::control_ipc::{self, ControlCommand, ControlResponse, DaemonStatus};
+use crate::event_source::{SourceReport, StatusHandle};
+use crate::ipc::ProtocolStats;
+
+/// Longest request line accepted, a full `UpdateConfig` fits easily
+const MAX_REQUEST_LEN: u64 = 64 * 1024;
+
+/// How long a request may wait for the GTK thread before it is answered with an error
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Replies are only built by the daemon, `chotopctl` just reads them
+impl ControlResponse {
+    pub fn ok() -> Self {
+        Self::Ok { data: None }
+    }
+
+    pub fn with_data<T: Serialize>(data: &T) -> Self {
+        match serde_json::to_value(data) {
+            Ok(data) => Self::Ok { data: Some(data) },
+            Err(e) => Self::error(format!("Failed to serialize reply: {}", e)),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::Error { message: message.into() }
+    }
+}
+
+/// A command for the GTK thread, answered through `reply`
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    pub reply: oneshot::Sender<ControlResponse>,
+}
+
+impl ControlRequest {
+    pub fn respond(self, response: ControlResponse) {
+        // The client may have hung up in the meantime
+        let _ = self.reply.send(response);
+    }
+}
+
+/// Control socket server. Status queries are answered on the server's own thread,
+/// everything else goes to the GTK thread as a `ControlRequest`.
+pub struct ControlIpcServer {
+    socket_path: PathBuf,
+    started: Instant,
+    protocol_stats: Arc<ProtocolStats>,
+    source_status: Vec<(&'static str, StatusHandle)>,
+}
+
+impl ControlIpcServer {
+    pub fn new(protocol_stats: Arc<ProtocolStats>, source_status: Vec<(&'static str, StatusHandle)>) -> Self {
+        let socket_path = control_ipc::socket_path();
+
+        // Remove old socket if it exists
+        if socket_path.exists() {
+            let _ = std::fs::remove_file(&socket_path);
+        }
+
+        Self {
+            socket_path,
+            started: Instant::now(),
+            protocol_stats,
+            source_status,
+        }
+    }
+
+    pub async fn run(self: Arc<Self>, requests: mpsc::Sender<ControlRequest>) -> std::io::Result<()> {
+        let listener = UnixListener::bind(&self.socket_path)?;
+        info!("Control IPC server listening on {:?}", self.socket_path);
+
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let server = self.clone();
+                    let requests = requests.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = server.handle_connection(stream, requests).await {
+                            warn!("Control connection failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    error!("Failed to accept control connection: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Answer requests until the client closes the connection
+    async fn handle_connection(
+        &self,
+        stream: UnixStream,
+        requests: mpsc::Sender<ControlRequest>,
+    ) -> std::io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+            let n = (&mut reader).take(MAX_REQUEST_LEN).read_line(&mut line).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            if !line.ends_with('\n') && n as u64 == MAX_REQUEST_LEN {
+                write_response(&mut writer, &ControlResponse::error("Request too long")).await?;
+                return Ok(());
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let command = match serde_json::from_str::<ControlCommand>(&line) {
+                Ok(command) => command,
+                Err(e) => {
+                    write_response(&mut writer, &ControlResponse::error(format!("Invalid request: {}", e))).await?;
+                    continue;
+                }
+            };
+            info!("Received control command: {:?}", command);
+
+            // Quitting takes the daemon down, so acknowledge first
+            if matches!(command, ControlCommand::Quit) {
+                write_response(&mut writer, &ControlResponse::ok()).await?;
+                let (reply, _) = oneshot::channel();
+                let _ = requests.send(ControlRequest { command, reply }).await;
+                return Ok(());
+            }
+
+            let response = self.answer(command, &requests).await;
+            write_response(&mut writer, &response).await?;
+        }
+    }
+
+    async fn answer(&self, command: ControlCommand, requests: &mpsc::Sender<ControlRequest>) -> ControlResponse {
+        match command {
+            ControlCommand::GetStatus => ControlResponse::with_data(&DaemonStatus {
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                pid: std::process::id(),
+                uptime_secs: self.started.elapsed().as_secs(),
+            }),
+            ControlCommand::GetProtocolStats => ControlResponse::with_data(&self.protocol_stats.snapshot()),
+            ControlCommand::GetSourceStatus => {
+                let reports: Vec<SourceReport> = self
+                    .source_status
+                    .iter()
+                    .map(|(name, status)| SourceReport {
+                        name: name.to_string(),
+                        status: status.get(),
+                    })
+                    .collect();
+                ControlResponse::with_data(&reports)
+            }
+            command => {
+                let (reply, response) = oneshot::channel();
+                if requests.send(ControlRequest { command, reply }).await.is_err() {
+                    return ControlResponse::error("Daemon is shutting down");
+                }
+                match tokio::time::timeout(REPLY_TIMEOUT, response).await {
+                    Ok(Ok(response)) => response,
+                    Ok(Err(_)) => ControlResponse::error("Request dropped by the daemon"),
+                    Err(_) => ControlResponse::error("Daemon did not answer in time"),
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ControlIpcServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.socket_path);
+    }
+}
+
+async fn write_response<W: AsyncWriteExt + Unpin>(writer: &mut W, response: &ControlResponse) -> std::io::Result<()> {
+    let mut data = serde_json::to_vec(response)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    data.push(b'\n');
+    writer.write_all(&data).await
+}
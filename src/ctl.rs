@@ -0,0 +1,321 @@
+// chotopctl never loads the config the daemon's way, which may rewrite the file
+#[allow(dead_code)]
+mod config;
+mod control_ipc;
+
+use config::Config;
+use control_ipc::{ControlCommand, ControlIpcClient, DaemonStatus};
+use serde_json::{json, Value};
+use std::process::ExitCode;
+use tracing::Level;
+use tracing_subscriber::FmtSubscriber;
+
+type CtlResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+const USAGE: &str = "\
+Usage: chotopctl [OPTIONS] <COMMAND>
+
+Controls the running chotop daemon over its control socket.
+
+Commands:
+  status                 Daemon version, uptime and event sources
+  roster                 Voice channel and users shown by the overlay
+  test-mode on|off       Show fake users, or go back to the real roster
+  reload                 Apply config.toml to the running daemon
+  set <KEY> <VALUE>      Change one setting of the running daemon (e.g. set position TopLeft)
+  show | hide | toggle   Show or hide the voice overlay
+  mute | deafen          Toggle mute or deafen in Discord
+  quit                   Stop the daemon
+
+Options:
+  --json       Print the daemon's reply data as JSON (commands without data print nothing)
+  --save       With `set`, also write the change to config.toml
+  -h, --help   Print this help";
+
+enum Action {
+    Status,
+    Roster,
+    TestMode(bool),
+    Reload,
+    Set { key: String, value: String },
+    Show,
+    Hide,
+    Toggle,
+    Mute,
+    Deafen,
+    Quit,
+}
+
+struct Args {
+    action: Action,
+    json: bool,
+    save: bool,
+}
+
+/// `None` when help was asked for
+fn parse_args() -> Result<Option<Args>, String> {
+    let mut json = false;
+    let mut save = false;
+    let mut words = Vec::new();
+
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--save" => save = true,
+            "-h" | "--help" => return Ok(None),
+            other if other.starts_with("--") => return Err(format!("unknown option: {}", other)),
+            _ => words.push(arg),
+        }
+    }
+
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    let action = match words.as_slice() {
+        ["status"] => Action::Status,
+        ["roster"] => Action::Roster,
+        ["test-mode", "on"] => Action::TestMode(true),
+        ["test-mode", "off"] => Action::TestMode(false),
+        ["test-mode", ..] => return Err("test-mode needs on or off".to_string()),
+        ["reload"] => Action::Reload,
+        ["set", key, value] => Action::Set {
+            key: key.to_string(),
+            value: value.to_string(),
+        },
+        ["set", ..] => return Err("set needs a key and a value".to_string()),
+        ["show"] => Action::Show,
+        ["hide"] => Action::Hide,
+        ["toggle"] => Action::Toggle,
+        ["mute"] => Action::Mute,
+        ["deafen"] => Action::Deafen,
+        ["quit"] => Action::Quit,
+        [] => return Err("missing command".to_string()),
+        [command, ..] => return Err(format!("unknown command: {}", command)),
+    };
+
+    if save && !matches!(action, Action::Set { .. }) {
+        return Err("--save only applies to set".to_string());
+    }
+
+    Ok(Some(Args { action, json, save }))
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    // Only warnings from the shared modules are worth showing, errors are printed by `main`
+    let subscriber = FmtSubscriber::builder()
+        .with_max_level(Level::WARN)
+        .with_writer(std::io::stderr)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber).expect("Failed to set subscriber");
+
+    let args = match parse_args() {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(&args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("chotopctl: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(args: &Args) -> CtlResult<()> {
+    match &args.action {
+        Action::Status => {
+            let status = query(ControlCommand::GetStatus).await?;
+            let sources = query(ControlCommand::GetSourceStatus).await?;
+            if args.json {
+                println!("{}", json!({ "daemon": status, "sources": sources }));
+            } else {
+                print_status(&serde_json::from_value(status)?, &sources);
+            }
+        }
+        Action::Roster => {
+            let roster = query(ControlCommand::GetRoster).await?;
+            if args.json {
+                println!("{}", roster);
+            } else {
+                print_roster(&roster);
+            }
+        }
+        Action::TestMode(on) => {
+            let command = if *on {
+                ControlCommand::EnableTestMode
+            } else {
+                ControlCommand::DisableTestMode
+            };
+            send(args, command, if *on { "Test mode on" } else { "Test mode off" }).await?;
+        }
+        Action::Reload => {
+            // A broken file is reported, never replaced by defaults
+            let config = Config::try_load()?;
+            let message = format!("Applied {}", Config::config_path().display());
+            send(args, ControlCommand::UpdateConfig(Box::new(config)), &message).await?;
+        }
+        Action::Set { key, value } => {
+            let current = query(ControlCommand::GetConfig).await?;
+            let config = set_key(current, key, value)?;
+            send(args, ControlCommand::UpdateConfig(Box::new(config.clone())), &format!("{} = {}", key, value)).await?;
+            if args.save {
+                config.save();
+            }
+        }
+        Action::Show => send(args, ControlCommand::ShowOverlay, "Overlay shown").await?,
+        Action::Hide => send(args, ControlCommand::HideOverlay, "Overlay hidden").await?,
+        Action::Toggle => {
+            let reply = query(ControlCommand::ToggleOverlay).await?;
+            if args.json {
+                println!("{}", reply);
+            } else if reply["visible"] == true {
+                println!("Overlay shown");
+            } else {
+                println!("Overlay hidden");
+            }
+        }
+        Action::Mute => send(args, ControlCommand::ToggleMute, "Mute toggled").await?,
+        Action::Deafen => send(args, ControlCommand::ToggleDeafen, "Deafen toggled").await?,
+        Action::Quit => send(args, ControlCommand::Quit, "Daemon stopped").await?,
+    }
+
+    Ok(())
+}
+
+/// Send a query and return the data of the reply
+async fn query(command: ControlCommand) -> CtlResult<Value> {
+    ControlIpcClient::query(command).await.map_err(daemon_error)
+}
+
+/// Send a command without reply data, confirming it unless `--json` is set
+async fn send(args: &Args, command: ControlCommand, message: &str) -> CtlResult<()> {
+    ControlIpcClient::send_command(command).await.map_err(daemon_error)?;
+    if !args.json {
+        println!("{}", message);
+    }
+    Ok(())
+}
+
+/// Turn a missing socket into a readable error
+fn daemon_error(e: std::io::Error) -> Box<dyn std::error::Error + Send + Sync> {
+    match e.kind() {
+        std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused => format!(
+            "daemon is not running (no control socket at {})",
+            control_ipc::socket_path().display()
+        )
+        .into(),
+        _ => e.into(),
+    }
+}
+
+/// Change one key of the daemon's config, reading `value` as whatever type the key has
+fn set_key(current: Value, key: &str, value: &str) -> CtlResult<Config> {
+    if current.get(key).is_none() {
+        return Err(format!("unknown setting: {}", key).into());
+    }
+    let mut table = toml::Table::try_from(serde_json::from_value::<Config>(current)?)?;
+
+    // `5`, `true` or `["Tcp"]` as written, or the plain text; only the one the key's type accepts
+    // is kept, so an id made of digits stays a string and `port abc` is an error
+    let literal = toml::from_str::<toml::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut parsed| parsed.remove("value"));
+    let mut first_error = None;
+    for candidate in literal.into_iter().chain([toml::Value::String(value.to_string())]) {
+        table.insert(key.to_string(), candidate);
+        match toml::from_str::<Config>(&toml::to_string(&table)?) {
+            Ok(config) => return Ok(config),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    let reason = first_error.map(|e| e.message().to_string()).unwrap_or_default();
+    Err(format!("invalid value for {}: {}", key, reason).into())
+}
+
+fn print_status(status: &DaemonStatus, sources: &Value) {
+    println!(
+        "chotop {} running (pid {}, up {})",
+        status.version,
+        status.pid,
+        format_duration(status.uptime_secs)
+    );
+    // The daemon may have fallen back to another port, which is what the plugin needs
+    let config = Config::try_load().unwrap_or_default();
+    println!("  plugin port: {}", config.active_port());
+
+    for source in sources.as_array().into_iter().flatten() {
+        let name = source["name"].as_str().unwrap_or("?");
+        let state = source["status"]["state"].as_str().unwrap_or("?");
+        match source["status"]["detail"].as_str() {
+            Some(detail) => println!("  {}: {} ({})", name, state, detail),
+            None => println!("  {}: {}", name, state),
+        }
+    }
+}
+
+fn print_roster(roster: &Value) {
+    let channels: Vec<String> = roster["channels"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|channel| match channel["channelName"].as_str() {
+            Some(name) => format!("#{}", name),
+            None => channel["channelId"].as_str().unwrap_or("?").to_string(),
+        })
+        .collect();
+
+    if channels.is_empty() {
+        println!("Not in a voice channel");
+        return;
+    }
+
+    let stage = if roster["channelType"] == "stage" { " (stage)" } else { "" };
+    let lost = if roster["sourceLost"] == true { " [plugin lost]" } else { "" };
+    println!("{}{}{}", channels.join(", "), stage, lost);
+
+    for user in roster["users"].as_array().into_iter().flatten() {
+        let name = user["nick"]
+            .as_str()
+            .filter(|nick| !nick.is_empty())
+            .or(user["username"].as_str())
+            .unwrap_or("?");
+
+        let flags: Vec<&str> = [
+            ("speaking", "speaking"),
+            ("mute", "muted"),
+            ("deaf", "deafened"),
+            ("streaming", "streaming"),
+            ("video", "video"),
+            ("handRaised", "hand raised"),
+        ]
+        .into_iter()
+        .filter(|(field, _)| user[*field] == true)
+        .map(|(_, label)| label)
+        .collect();
+
+        if flags.is_empty() {
+            println!("  {}", name);
+        } else {
+            println!("  {:<24} {}", name, flags.join(", "));
+        }
+    }
+}
+
+/// "1h 02m", "5m 03s" or "42s"
+fn format_duration(secs: u64) -> String {
+    match (secs / 3600, secs % 3600 / 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, m, _) => format!("{}h {:02}m", h, m),
+    }
+}
@@ -1,7 +1,95 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
-/// Represents a user in a Discord voice channel (Orbolay protocol)
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+use crate::config::Position;
+
+/// Optional protocol field that keeps an omitted key apart from an explicit `null`.
+/// Use with `#[serde(default)]` so a missing key deserializes as `Absent`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Field<T> {
+    /// Key not present in the payload
+    #[default]
+    Absent,
+    /// Key present with a `null` value
+    Null,
+    /// Key present with a value
+    Value(T),
+}
+
+impl<T> Field<T> {
+    pub fn into_option(self) -> Option<T> {
+        match self {
+            Field::Value(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Overwrite `target` unless the key was absent
+    pub fn apply_to(self, target: &mut Option<T>) {
+        match self {
+            Field::Absent => {}
+            Field::Null => *target = None,
+            Field::Value(value) => *target = Some(value),
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Field<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Only called when the key is present, so `None` here is an explicit null
+        Ok(match Option::<T>::deserialize(deserializer)? {
+            Some(value) => Field::Value(value),
+            None => Field::Null,
+        })
+    }
+}
+
+/// Protocol version spoken by this daemon. Bumped only for breaking changes;
+/// additive features are negotiated through `Capability` instead.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Optional capabilities this daemon advertises in its HELLO
+pub const DAEMON_CAPABILITIES: &[Capability] = &[
+    Capability::BidirectionalControl,
+    Capability::VoiceSnapshot,
+    Capability::RichNotifications,
+    Capability::ChannelChat,
+];
+
+/// Optional protocol features negotiated during the HELLO exchange
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Capability {
+    /// Plugin accepts voice control commands from the daemon
+    BidirectionalControl,
+    /// Notifications carry structured author/guild/channel fields
+    RichNotifications,
+    /// Plugin streams per-user audio levels
+    AudioLevels,
+    /// Plugin answers REQUEST_SNAPSHOT with a full VOICE_SNAPSHOT
+    VoiceSnapshot,
+    /// Plugin forwards the voice channel's text chat as CHANNEL_MESSAGE
+    ChannelChat,
+    /// Capability from a newer peer that this daemon doesn't know
+    #[serde(other)]
+    Unknown,
+}
+
+/// Handshake message, sent by both sides when a connection opens
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HelloMessage {
+    #[serde(rename = "protocolVersion")]
+    pub protocol_version: u32,
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+    /// Pairing token (plugin to daemon only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+/// Represents a user in a Discord voice channel (Orbolay protocol).
+/// `mute` and `deaf` are the combined states; the finer flags are optional
+/// and default to false for plugins that don't send them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct VoiceUser {
     #[serde(rename = "userId")]
     pub user_id: String,
@@ -14,26 +102,66 @@ pub struct VoiceUser {
     pub mute: bool,
     pub streaming: bool,
     pub speaking: bool,
+    #[serde(rename = "selfMute", default)]
+    pub self_mute: bool,
+    #[serde(rename = "selfDeaf", default)]
+    pub self_deaf: bool,
+    /// Muted by a server moderator
+    #[serde(rename = "serverMute", default)]
+    pub server_mute: bool,
+    /// Deafened by a server moderator
+    #[serde(rename = "serverDeaf", default)]
+    pub server_deaf: bool,
+    /// Not allowed to speak (AFK channel, stage audience)
+    #[serde(default)]
+    pub suppress: bool,
+    /// Camera on
+    #[serde(default)]
+    pub video: bool,
+    #[serde(rename = "prioritySpeaker", default)]
+    pub priority_speaker: bool,
+    /// Stage audience member asking to speak
+    #[serde(rename = "handRaised", default)]
+    pub hand_raised: bool,
+    /// Server nickname, shown instead of `username`
+    #[serde(default)]
+    pub nick: Option<String>,
+    /// Per-server avatar hash, used together with `guildId`
+    #[serde(rename = "guildAvatar", default)]
+    pub guild_avatar: Option<String>,
+    #[serde(rename = "guildId", default)]
+    pub guild_id: Option<String>,
+    /// CSS color of the user's highest colored role (e.g. "#e91e63")
+    #[serde(rename = "roleColor", default)]
+    pub role_color: Option<String>,
 }
 
 impl VoiceUser {
+    /// Nickname when set, global username otherwise
+    pub fn display_name(&self) -> &str {
+        self.nick
+            .as_deref()
+            .filter(|nick| !nick.is_empty())
+            .unwrap_or(&self.username)
+    }
+
+    /// Avatar hash to show and the guild it belongs to, preferring the per-server avatar
+    pub fn avatar(&self) -> Option<(&str, Option<&str>)> {
+        match (&self.guild_avatar, &self.guild_id) {
+            (Some(hash), Some(guild_id)) => Some((hash, Some(guild_id))),
+            _ => self.avatar_url.as_deref().map(|hash| (hash, None)),
+        }
+    }
+
     /// Returns the full avatar URL
     pub fn full_avatar_url(&self) -> Option<String> {
-        self.avatar_url.as_ref().map(|hash| {
-            if hash.starts_with("http") {
-                hash.clone()
-            } else {
-                format!(
-                    "https://cdn.discordapp.com/avatars/{}/{}.png?size=64",
-                    self.user_id, hash
-                )
-            }
-        })
+        self.avatar()
+            .map(|(hash, guild_id)| avatar_url(&self.user_id, guild_id, hash))
     }
 
     /// Returns initials for users without avatars
     pub fn initials(&self) -> String {
-        self.username
+        self.display_name()
             .split_whitespace()
             .filter_map(|word| word.chars().next())
             .take(2)
@@ -42,10 +170,24 @@ impl VoiceUser {
     }
 }
 
+/// CDN URL for an avatar hash, a guild avatar when `guild_id` is given
+pub fn avatar_url(user_id: &str, guild_id: Option<&str>, hash: &str) -> String {
+    match guild_id {
+        _ if hash.starts_with("http") => hash.to_string(),
+        Some(guild_id) => format!(
+            "https://cdn.discordapp.com/guilds/{}/users/{}/avatars/{}.png?size=64",
+            guild_id, user_id, hash
+        ),
+        None => format!(
+            "https://cdn.discordapp.com/avatars/{}/{}.png?size=64",
+            user_id, hash
+        ),
+    }
+}
+
 /// Config message from Orbolay plugin
 #[derive(Debug, Clone, Deserialize)]
 pub struct ConfigMessage {
-    pub cmd: String,
     pub port: Option<u16>,
     #[serde(rename = "userId")]
     pub user_id: Option<String>,
@@ -57,44 +199,124 @@ pub struct ConfigMessage {
     pub voice_semitransparent: Option<bool>,
     #[serde(rename = "messagesSemitransparent")]
     pub messages_semitransparent: Option<bool>,
+    /// Pairing token, for plugins that send REGISTER_CONFIG as their first frame
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// Kind of voice channel, decides how the roster is laid out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChannelType {
+    /// Stage channel: speakers are shown, the audience (`suppress`) is collapsed
+    Stage,
+    /// Regular voice channel (also used for types this daemon doesn't know)
+    #[default]
+    #[serde(other)]
+    Voice,
+}
+
+impl ConfigMessage {
+    /// Voice overlay corner picked in the plugin (`userAlignment`)
+    pub fn user_position(&self) -> Option<Position> {
+        self.user_alignment.as_deref().and_then(parse_alignment)
+    }
+
+    /// Notification corner picked in the plugin (`messageAlignment`)
+    pub fn message_position(&self) -> Option<Position> {
+        self.message_alignment.as_deref().and_then(parse_alignment)
+    }
+}
+
+/// Parse a plugin alignment setting such as "topleft" or "bottom-right"
+fn parse_alignment(alignment: &str) -> Option<Position> {
+    let normalized: String = alignment
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    match normalized.as_str() {
+        "topleft" => Some(Position::TopLeft),
+        "topright" => Some(Position::TopRight),
+        "bottomleft" => Some(Position::BottomLeft),
+        "bottomright" => Some(Position::BottomRight),
+        _ => None,
+    }
 }
 
 /// Channel joined message
 #[derive(Debug, Clone, Deserialize)]
 pub struct ChannelJoinedMessage {
-    pub cmd: String,
     pub states: Vec<VoiceUser>,
     #[serde(rename = "channelName")]
     pub channel_name: Option<String>,
+    #[serde(rename = "channelType", default)]
+    pub channel_type: ChannelType,
+}
+
+/// Full voice state, sent by the plugin in reply to REQUEST_SNAPSHOT
+#[derive(Debug, Clone, Deserialize)]
+pub struct VoiceSnapshotMessage {
+    /// Current voice channel, `null` when the user is not in voice
+    #[serde(rename = "channelId")]
+    pub channel_id: Option<String>,
+    #[serde(rename = "channelName")]
+    pub channel_name: Option<String>,
+    #[serde(rename = "channelType", default)]
+    pub channel_type: ChannelType,
+    #[serde(default)]
+    pub states: Vec<VoiceUser>,
 }
 
 /// Voice state update message
 #[derive(Debug, Clone, Deserialize)]
 pub struct VoiceStateUpdateMessage {
-    pub cmd: String,
     pub state: VoiceUserPartial,
 }
 
-/// Partial voice user update (for speaking/state changes)
-#[derive(Debug, Clone, Deserialize)]
+/// Partial voice user update (for speaking/state changes).
+/// Speaking updates omit `channelId`, full state updates send `null` on disconnect.
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct VoiceUserPartial {
     #[serde(rename = "userId")]
     pub user_id: String,
     pub username: Option<String>,
-    #[serde(rename = "avatarUrl")]
-    pub avatar_url: Option<String>,
-    #[serde(rename = "channelId")]
-    pub channel_id: Option<String>,
+    #[serde(rename = "avatarUrl", default)]
+    pub avatar_url: Field<String>,
+    #[serde(rename = "channelId", default)]
+    pub channel_id: Field<String>,
     pub deaf: Option<bool>,
     pub mute: Option<bool>,
     pub streaming: Option<bool>,
     pub speaking: Option<bool>,
+    #[serde(rename = "selfMute")]
+    pub self_mute: Option<bool>,
+    #[serde(rename = "selfDeaf")]
+    pub self_deaf: Option<bool>,
+    #[serde(rename = "serverMute")]
+    pub server_mute: Option<bool>,
+    #[serde(rename = "serverDeaf")]
+    pub server_deaf: Option<bool>,
+    pub suppress: Option<bool>,
+    pub video: Option<bool>,
+    #[serde(rename = "prioritySpeaker")]
+    pub priority_speaker: Option<bool>,
+    #[serde(rename = "handRaised")]
+    pub hand_raised: Option<bool>,
+    #[serde(default)]
+    pub nick: Field<String>,
+    #[serde(rename = "guildAvatar", default)]
+    pub guild_avatar: Field<String>,
+    #[serde(rename = "guildId", default)]
+    pub guild_id: Field<String>,
+    #[serde(rename = "roleColor", default)]
+    pub role_color: Field<String>,
 }
 
 /// Message notification
 #[derive(Debug, Clone, Deserialize)]
 pub struct MessageNotification {
-    pub cmd: String,
     pub message: NotificationContent,
 }
 
@@ -105,20 +327,307 @@ pub struct NotificationContent {
     pub icon: Option<String>,
     #[serde(rename = "channelId")]
     pub channel_id: Option<String>,
+    // Structured fields below are only sent by plugins with `richNotifications`
+    #[serde(rename = "authorId", default)]
+    pub author_id: Option<String>,
+    #[serde(rename = "authorName", default)]
+    pub author_name: Option<String>,
+    #[serde(rename = "guildName", default)]
+    pub guild_name: Option<String>,
+    #[serde(rename = "channelName", default)]
+    pub channel_name: Option<String>,
+    #[serde(rename = "messageId", default)]
+    pub message_id: Option<String>,
+    /// Milliseconds since the Unix epoch
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    /// Direct message rather than a guild channel
+    #[serde(rename = "isDm", default)]
+    pub is_dm: bool,
+    /// The message mentions the local user
+    #[serde(default)]
+    pub mentioned: bool,
+    #[serde(default)]
+    pub attachments: Vec<NotificationAttachment>,
+}
+
+/// File attached to a notified message
+#[derive(Debug, Clone, Deserialize)]
+pub struct NotificationAttachment {
+    pub filename: String,
+    #[serde(rename = "contentType", default)]
+    pub content_type: Option<String>,
+}
+
+impl NotificationContent {
+    /// Where the message was posted, e.g. "#general · Our Guild" or "Direct Message"
+    pub fn subtitle(&self) -> Option<String> {
+        if self.is_dm {
+            return Some("Direct Message".to_string());
+        }
+        let parts: Vec<String> = [
+            self.channel_name.as_ref().map(|name| format!("#{}", name)),
+            self.guild_name.clone(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        (!parts.is_empty()).then(|| parts.join(" · "))
+    }
+
+    /// One line summarizing the attachments, e.g. "🖼 cat.png" or "📎 3 attachments"
+    pub fn attachments_summary(&self) -> Option<String> {
+        match self.attachments.as_slice() {
+            [] => None,
+            [attachment] => {
+                let is_image = attachment
+                    .content_type
+                    .as_deref()
+                    .is_some_and(|t| t.starts_with("image/"));
+                let icon = if is_image { "🖼" } else { "📎" };
+                Some(format!("{} {}", icon, attachment.filename))
+            }
+            attachments => Some(format!("📎 {} attachments", attachments.len())),
+        }
+    }
+}
+
+/// Channel chat message
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChannelMessage {
+    pub message: ChatMessage,
 }
 
-/// Generic incoming message (for parsing cmd field first)
+/// Text message posted in a voice channel's chat
 #[derive(Debug, Clone, Deserialize)]
-pub struct GenericMessage {
-    pub cmd: String,
+pub struct ChatMessage {
+    #[serde(rename = "channelId")]
+    pub channel_id: String,
+    #[serde(rename = "authorName")]
+    pub author_name: String,
+    #[serde(default)]
+    pub content: String,
+}
+
+/// Incoming message from the Orbolay plugin, tagged by its `cmd` field
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "cmd", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum IncomingMessage {
+    Hello(HelloMessage),
+    RegisterConfig(ConfigMessage),
+    ChannelJoined(ChannelJoinedMessage),
+    ChannelLeft,
+    VoiceStateUpdate(VoiceStateUpdateMessage),
+    VoiceSnapshot(VoiceSnapshotMessage),
+    MessageNotification(MessageNotification),
+    ChannelMessage(ChannelMessage),
+    /// Command not known to this daemon version (kept for forward compatibility)
+    #[serde(skip_deserializing)]
+    Unknown(String),
+}
+
+impl IncomingMessage {
+    /// Commands handled by this daemon version
+    const COMMANDS: &'static [&'static str] = &[
+        "HELLO",
+        "REGISTER_CONFIG",
+        "CHANNEL_JOINED",
+        "CHANNEL_LEFT",
+        "VOICE_STATE_UPDATE",
+        "VOICE_SNAPSHOT",
+        "MESSAGE_NOTIFICATION",
+        "CHANNEL_MESSAGE",
+    ];
+
+    /// Parse a raw text frame, reporting exactly why a frame was rejected
+    pub fn parse(text: &str) -> Result<Self, ProtocolError> {
+        let value: serde_json::Value =
+            serde_json::from_str(text).map_err(ProtocolError::InvalidJson)?;
+
+        let cmd = match value.get("cmd") {
+            Some(serde_json::Value::String(cmd)) => cmd.clone(),
+            _ => return Err(ProtocolError::MissingCommand),
+        };
+
+        if !Self::COMMANDS.contains(&cmd.as_str()) {
+            return Ok(Self::Unknown(cmd));
+        }
+
+        Self::deserialize(value).map_err(|source| ProtocolError::Malformed { cmd, source })
+    }
+}
+
+/// Reason an incoming frame could not be parsed
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// Frame is not valid JSON
+    InvalidJson(serde_json::Error),
+    /// Frame has no string `cmd` field
+    MissingCommand,
+    /// Known command with missing or mistyped fields
+    Malformed {
+        cmd: String,
+        source: serde_json::Error,
+    },
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidJson(e) => write!(f, "invalid JSON: {}", e),
+            Self::MissingCommand => write!(f, "missing \"cmd\" field"),
+            Self::Malformed { cmd, source } => write!(f, "malformed {}: {}", cmd, source),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// Messages sent back to the Orbolay plugin
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "cmd", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum OutgoingMessage {
+    Hello(HelloMessage),
+    /// Ask the plugin for a full VOICE_SNAPSHOT
+    RequestSnapshot,
+    ToggleMute,
+    ToggleDeaf,
+    Disconnect,
+    MoveToChannel {
+        #[serde(rename = "channelId")]
+        channel_id: String,
+    },
 }
 
 /// Events sent to the UI
 #[derive(Debug, Clone)]
 pub enum OverlayEvent {
-    ChannelJoined(Vec<VoiceUser>, String), // users, channel_name
+    ChannelJoined(Vec<VoiceUser>, String, ChannelType), // users, channel_name, channel_type
     ChannelLeft,
     VoiceStateUpdate(VoiceUserPartial),
+    /// A snapshot was requested, partial updates should not invent users until it arrives
+    SnapshotRequested,
+    VoiceSnapshot(VoiceSnapshotMessage),
     ConfigReceived(ConfigMessage),
     MessageNotification(NotificationContent),
+    ChannelMessage(ChatMessage),
+    /// The plugin stopped responding and did not reconnect within the grace period
+    SourceLost,
+    /// The connection registered under a new session id; carries the previous one
+    SessionRenamed(String),
+    /// The plugin closed its connection and nothing else carries the session
+    SessionClosed,
+}
+
+/// Command for the plugin connection registered as `session_id`
+#[derive(Debug, Clone)]
+pub struct PluginCommand {
+    pub session_id: String,
+    pub message: OutgoingMessage,
+}
+
+/// Overlay event tagged with the plugin session it came from
+#[derive(Debug, Clone)]
+pub struct SessionEvent {
+    /// Discord user id from REGISTER_CONFIG (a per-connection id until it arrives)
+    pub session_id: String,
+    pub event: OverlayEvent,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel_id(state: &str) -> Field<String> {
+        serde_json::from_str::<VoiceUserPartial>(state).unwrap().channel_id
+    }
+
+    #[test]
+    fn field_absent_null_and_value() {
+        assert_eq!(channel_id(r#"{"userId":"1","speaking":true}"#), Field::Absent);
+        assert_eq!(channel_id(r#"{"userId":"1","channelId":null}"#), Field::Null);
+        assert_eq!(
+            channel_id(r#"{"userId":"1","channelId":"42"}"#),
+            Field::Value("42".to_string())
+        );
+    }
+
+    #[test]
+    fn field_apply_to() {
+        let mut channel = Some("42".to_string());
+        Field::Absent.apply_to(&mut channel);
+        assert_eq!(channel.as_deref(), Some("42"));
+        Field::Value("7".to_string()).apply_to(&mut channel);
+        assert_eq!(channel.as_deref(), Some("7"));
+        Field::Null.apply_to(&mut channel);
+        assert_eq!(channel, None);
+    }
+
+    #[test]
+    fn parse_known_command() {
+        let message = IncomingMessage::parse(r#"{"cmd":"CHANNEL_LEFT"}"#).unwrap();
+        assert!(matches!(message, IncomingMessage::ChannelLeft));
+    }
+
+    #[test]
+    fn parse_unknown_command() {
+        let message = IncomingMessage::parse(r#"{"cmd":"FUTURE_THING","payload":1}"#).unwrap();
+        assert!(matches!(message, IncomingMessage::Unknown(cmd) if cmd == "FUTURE_THING"));
+    }
+
+    #[test]
+    fn parse_invalid_json() {
+        let error = IncomingMessage::parse("{\"cmd\":").unwrap_err();
+        assert!(matches!(error, ProtocolError::InvalidJson(_)));
+    }
+
+    #[test]
+    fn parse_missing_command() {
+        for text in [r#"{"state":{}}"#, r#"{"cmd":42}"#, "[]"] {
+            let error = IncomingMessage::parse(text).unwrap_err();
+            assert!(matches!(error, ProtocolError::MissingCommand), "{}", text);
+        }
+    }
+
+    /// Exhaustive, so a new variant doesn't compile until it has a sample below
+    fn command_name(message: &IncomingMessage) -> &str {
+        match message {
+            IncomingMessage::Hello(_) => "HELLO",
+            IncomingMessage::RegisterConfig(_) => "REGISTER_CONFIG",
+            IncomingMessage::ChannelJoined(_) => "CHANNEL_JOINED",
+            IncomingMessage::ChannelLeft => "CHANNEL_LEFT",
+            IncomingMessage::VoiceStateUpdate(_) => "VOICE_STATE_UPDATE",
+            IncomingMessage::VoiceSnapshot(_) => "VOICE_SNAPSHOT",
+            IncomingMessage::MessageNotification(_) => "MESSAGE_NOTIFICATION",
+            IncomingMessage::ChannelMessage(_) => "CHANNEL_MESSAGE",
+            IncomingMessage::Unknown(cmd) => cmd,
+        }
+    }
+
+    #[test]
+    fn commands_match_the_enum() {
+        let samples = [
+            r#"{"cmd":"HELLO","protocolVersion":1}"#,
+            r#"{"cmd":"REGISTER_CONFIG"}"#,
+            r#"{"cmd":"CHANNEL_JOINED","states":[]}"#,
+            r#"{"cmd":"CHANNEL_LEFT"}"#,
+            r#"{"cmd":"VOICE_STATE_UPDATE","state":{"userId":"1"}}"#,
+            r#"{"cmd":"VOICE_SNAPSHOT","channelId":null}"#,
+            r#"{"cmd":"MESSAGE_NOTIFICATION","message":{"title":"t","body":"b"}}"#,
+            r#"{"cmd":"CHANNEL_MESSAGE","message":{"channelId":"1","authorName":"a"}}"#,
+        ];
+        let mut parsed = Vec::new();
+        for text in samples {
+            let message = IncomingMessage::parse(text).unwrap();
+            assert!(!matches!(message, IncomingMessage::Unknown(_)), "{}", text);
+            parsed.push(command_name(&message).to_string());
+        }
+        assert_eq!(parsed, IncomingMessage::COMMANDS);
+    }
+
+    #[test]
+    fn parse_malformed_command() {
+        let error = IncomingMessage::parse(r#"{"cmd":"VOICE_STATE_UPDATE","state":{"speaking":true}}"#).unwrap_err();
+        assert!(matches!(error, ProtocolError::Malformed { cmd, .. } if cmd == "VOICE_STATE_UPDATE"));
+    }
 }
@@ -0,0 +1,748 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::config::Config;
+use crate::discord_data::{ChannelType, Field, OverlayEvent, SessionEvent, VoiceUser, VoiceUserPartial};
+use crate::event_source::{EventSource, SourceStatus, SourceThread, StatusHandle};
+
+type RpcResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// IPC frame opcodes
+const OP_HANDSHAKE: u32 = 0;
+const OP_FRAME: u32 = 1;
+const OP_CLOSE: u32 = 2;
+const OP_PING: u32 = 3;
+const OP_PONG: u32 = 4;
+
+const RPC_VERSION: u32 = 1;
+/// Refuse frames larger than this, the client never sends anything close
+const MAX_FRAME_SIZE: usize = 1024 * 1024;
+/// Discord's channel type for stage channels
+const STAGE_CHANNEL_TYPE: u8 = 13;
+/// Wait between connection attempts while Discord is not running
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Events we follow for the selected voice channel
+const CHANNEL_EVENTS: [&str; 5] = [
+    "VOICE_STATE_CREATE",
+    "VOICE_STATE_UPDATE",
+    "VOICE_STATE_DELETE",
+    "SPEAKING_START",
+    "SPEAKING_STOP",
+];
+
+/// Event source reading voice state from the official Discord client's local RPC socket
+pub struct DiscordRpcSource {
+    client: DiscordRpcClient,
+    status: StatusHandle,
+    thread: Option<SourceThread>,
+}
+
+impl DiscordRpcSource {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            client: DiscordRpcClient {
+                socket_path: config.discord_rpc_socket.as_ref().map(PathBuf::from),
+                client_id: config.discord_rpc_client_id.clone(),
+                token_url: config.discord_rpc_token_url.clone(),
+                token_path: Config::config_path().with_file_name("discord-rpc-token"),
+            },
+            status: StatusHandle::default(),
+            thread: None,
+        }
+    }
+}
+
+impl EventSource for DiscordRpcSource {
+    fn name(&self) -> &'static str {
+        "discord-rpc"
+    }
+
+    fn start(&mut self, tx: mpsc::Sender<SessionEvent>) {
+        if self.thread.is_some() {
+            return;
+        }
+
+        self.status.set(SourceStatus::Starting);
+        let task = self.client.clone().run(tx, self.status.clone());
+        self.thread = Some(SourceThread::spawn(self.name(), task));
+    }
+
+    fn stop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            thread.stop();
+        }
+        self.status.set(SourceStatus::Stopped);
+    }
+
+    fn status(&self) -> StatusHandle {
+        self.status.clone()
+    }
+}
+
+/// Connection settings for the Discord client's RPC socket
+#[derive(Debug, Clone)]
+struct DiscordRpcClient {
+    /// Fixed socket instead of searching `$XDG_RUNTIME_DIR` (e.g. a local stand-in)
+    socket_path: Option<PathBuf>,
+    client_id: String,
+    token_url: String,
+    /// Access token cache, next to config.toml so Discord only asks for approval once
+    token_path: PathBuf,
+}
+
+impl DiscordRpcClient {
+    /// Connect to Discord whenever it is running and forward voice events until it goes away
+    async fn run(self, tx: mpsc::Sender<SessionEvent>, status: StatusHandle) {
+        loop {
+            match self.find_socket() {
+                Some(path) => {
+                    let mut session_id = None;
+                    match self.run_session(&path, &tx, &status, &mut session_id).await {
+                        Ok(()) => {
+                            info!("Discord RPC connection closed");
+                            status.set(SourceStatus::Waiting("Discord closed the connection".to_string()));
+                        }
+                        Err(e) => {
+                            warn!("Discord RPC error: {}", e);
+                            status.set(SourceStatus::Waiting(format!("reconnecting after error: {}", e)));
+                        }
+                    }
+
+                    // Discord quit or restarted: dim its roster until it is back, like a lost plugin
+                    if let Some(session_id) = session_id {
+                        let _ = tx
+                            .send(SessionEvent {
+                                session_id,
+                                event: OverlayEvent::SourceLost,
+                            })
+                            .await;
+                    }
+                }
+                None => {
+                    debug!("No Discord RPC socket found, is Discord running?");
+                    status.set(SourceStatus::Waiting("Discord is not running".to_string()));
+                }
+            }
+
+            if tx.is_closed() {
+                return;
+            }
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    /// `discord-ipc-0` to `discord-ipc-9`, in the runtime dir and the Flatpak/Snap sandboxes
+    fn find_socket(&self) -> Option<PathBuf> {
+        if let Some(path) = &self.socket_path {
+            return path.exists().then(|| path.clone());
+        }
+
+        let runtime_dir = Config::runtime_dir();
+        let dirs = [
+            runtime_dir.clone(),
+            runtime_dir.join("app/com.discordapp.Discord"),
+            runtime_dir.join("snap.discord"),
+        ];
+
+        dirs.iter()
+            .flat_map(|dir| (0..10).map(move |n| dir.join(format!("discord-ipc-{}", n))))
+            .find(|path| path.exists())
+    }
+
+    /// Follow one Discord connection; `session` is set once its events start flowing
+    async fn run_session(
+        &self,
+        path: &Path,
+        tx: &mpsc::Sender<SessionEvent>,
+        status: &StatusHandle,
+        session: &mut Option<String>,
+    ) -> RpcResult<()> {
+        let stream = UnixStream::connect(path).await?;
+        let mut rpc = RpcConnection::new(stream);
+        info!("Connected to Discord RPC at {:?}", path);
+
+        rpc.send(OP_HANDSHAKE, &json!({ "v": RPC_VERSION, "client_id": self.client_id })).await?;
+        let ready = rpc.next_event().await?;
+        if ready["evt"] != "READY" {
+            return Err(format!("unexpected handshake reply: {}", ready).into());
+        }
+
+        // Same session id as the plugin would register, so `pinned_session` works for both
+        let session_id = ready["data"]["user"]["id"]
+            .as_str()
+            .unwrap_or("discord-rpc")
+            .to_string();
+
+        self.authenticate(&mut rpc).await?;
+        info!("Discord RPC authenticated as session {}", session_id);
+        status.set(SourceStatus::Running(format!("connected to {}", path.display())));
+        *session = Some(session_id.clone());
+
+        let mut source = RpcSession {
+            session_id,
+            channel_id: None,
+            tx,
+        };
+
+        rpc.call("SUBSCRIBE", json!({}), Some("VOICE_CHANNEL_SELECT")).await?;
+        let selected = rpc.call("GET_SELECTED_VOICE_CHANNEL", json!({}), None).await?;
+        source.on_channel(&mut rpc, selected).await?;
+
+        loop {
+            let event = rpc.next_event().await?;
+            source.on_event(&mut rpc, event).await?;
+        }
+    }
+
+    /// Use the cached access token, or ask Discord (which shows an approval prompt) for a new one
+    async fn authenticate(&self, rpc: &mut RpcConnection) -> RpcResult<()> {
+        if let Ok(token) = fs::read_to_string(&self.token_path) {
+            match rpc.call("AUTHENTICATE", json!({ "access_token": token.trim() }), None).await {
+                Ok(_) => return Ok(()),
+                Err(e) => {
+                    warn!("Cached Discord RPC token rejected ({}), authorizing again", e);
+                    let _ = fs::remove_file(&self.token_path);
+                }
+            }
+        }
+
+        info!("Asking Discord to authorize chotop, approve the prompt in the Discord client");
+        let authorize = rpc
+            .call(
+                "AUTHORIZE",
+                json!({
+                    "client_id": self.client_id,
+                    // Voice channel and speaking events only need the base RPC scope
+                    "scopes": ["rpc"],
+                    "prompt": "none",
+                }),
+                None,
+            )
+            .await?;
+        let code = authorize["code"].as_str().ok_or("AUTHORIZE reply has no code")?;
+
+        let token = self.exchange_code(code).await?;
+        rpc.call("AUTHENTICATE", json!({ "access_token": token }), None).await?;
+        self.save_token(&token);
+        Ok(())
+    }
+
+    async fn exchange_code(&self, code: &str) -> RpcResult<String> {
+        let response = reqwest::Client::new()
+            .post(&self.token_url)
+            .header("Content-Type", "application/json")
+            .body(json!({ "code": code }).to_string())
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        let body: Value = serde_json::from_str(&response)?;
+        body["access_token"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| "token reply has no access_token".into())
+    }
+
+    fn save_token(&self, token: &str) {
+        if let Some(parent) = self.token_path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+
+        // Only the owner may read the token
+        let written = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&self.token_path)
+            .and_then(|mut file| file.write_all(token.as_bytes()));
+
+        if let Err(e) = written {
+            warn!("Failed to save Discord RPC token: {}", e);
+        }
+    }
+}
+
+/// Framed JSON connection to the Discord client
+struct RpcConnection {
+    stream: UnixStream,
+    next_nonce: u64,
+    /// Events that arrived while waiting for a command reply
+    pending: VecDeque<Value>,
+}
+
+impl RpcConnection {
+    fn new(stream: UnixStream) -> Self {
+        Self {
+            stream,
+            next_nonce: 1,
+            pending: VecDeque::new(),
+        }
+    }
+
+    async fn send(&mut self, opcode: u32, payload: &Value) -> RpcResult<()> {
+        let body = serde_json::to_vec(payload)?;
+        let mut frame = Vec::with_capacity(8 + body.len());
+        frame.extend_from_slice(&opcode.to_le_bytes());
+        frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&body);
+        self.stream.write_all(&frame).await?;
+        Ok(())
+    }
+
+    /// Read the next JSON frame, answering pings on the way
+    async fn recv(&mut self) -> RpcResult<Value> {
+        loop {
+            let mut header = [0u8; 8];
+            self.stream.read_exact(&mut header).await?;
+            let opcode = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+            let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+            if length > MAX_FRAME_SIZE {
+                return Err(format!("frame of {} bytes is too large", length).into());
+            }
+
+            let mut body = vec![0u8; length];
+            self.stream.read_exact(&mut body).await?;
+            let payload: Value = serde_json::from_slice(&body)?;
+
+            match opcode {
+                OP_FRAME => return Ok(payload),
+                OP_PING => self.send(OP_PONG, &payload).await?,
+                OP_PONG => {}
+                OP_CLOSE => {
+                    return Err(format!("Discord closed the connection: {}", payload["message"]).into())
+                }
+                other => debug!("Ignoring RPC frame with opcode {}", other),
+            }
+        }
+    }
+
+    async fn next_event(&mut self) -> RpcResult<Value> {
+        match self.pending.pop_front() {
+            Some(event) => Ok(event),
+            None => self.recv().await,
+        }
+    }
+
+    /// Send a command and wait for its reply, keeping events that arrive in between
+    async fn call(&mut self, cmd: &str, args: Value, evt: Option<&str>) -> RpcResult<Value> {
+        let nonce = self.next_nonce.to_string();
+        self.next_nonce += 1;
+
+        let mut payload = json!({ "cmd": cmd, "args": args, "nonce": nonce });
+        if let Some(evt) = evt {
+            payload["evt"] = json!(evt);
+        }
+        self.send(OP_FRAME, &payload).await?;
+
+        loop {
+            let mut reply = self.recv().await?;
+            if reply["nonce"] != nonce.as_str() {
+                self.pending.push_back(reply);
+                continue;
+            }
+            if reply["evt"] == "ERROR" {
+                return Err(format!("{} failed: {}", cmd, reply["data"]["message"]).into());
+            }
+            return Ok(reply["data"].take());
+        }
+    }
+}
+
+/// Voice channel as returned by GET_SELECTED_VOICE_CHANNEL
+#[derive(Debug, Deserialize)]
+struct RpcChannel {
+    id: String,
+    #[serde(default)]
+    name: String,
+    /// Discord channel type, 13 for stage channels
+    #[serde(rename = "type", default)]
+    kind: u8,
+    #[serde(default)]
+    voice_states: Vec<RpcVoiceState>,
+}
+
+/// One member of a voice channel, also the payload of VOICE_STATE_* events
+#[derive(Debug, Deserialize)]
+struct RpcVoiceState {
+    nick: Option<String>,
+    #[serde(default)]
+    voice_state: RpcVoiceFlags,
+    user: RpcUser,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RpcVoiceFlags {
+    #[serde(default)]
+    mute: bool,
+    #[serde(default)]
+    deaf: bool,
+    #[serde(default)]
+    self_mute: bool,
+    #[serde(default)]
+    self_deaf: bool,
+    #[serde(default)]
+    self_stream: bool,
+    #[serde(default)]
+    self_video: bool,
+    #[serde(default)]
+    suppress: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcUser {
+    id: String,
+    username: String,
+    global_name: Option<String>,
+    avatar: Option<String>,
+}
+
+impl RpcVoiceState {
+    fn into_voice_user(self, channel_id: &str) -> VoiceUser {
+        VoiceUser {
+            username: self.user.global_name.unwrap_or(self.user.username),
+            user_id: self.user.id,
+            avatar_url: self.user.avatar,
+            channel_id: Some(channel_id.to_string()),
+            deaf: self.voice_state.deaf || self.voice_state.self_deaf,
+            mute: self.voice_state.mute || self.voice_state.self_mute,
+            streaming: self.voice_state.self_stream,
+            speaking: false,
+            self_mute: self.voice_state.self_mute,
+            self_deaf: self.voice_state.self_deaf,
+            server_mute: self.voice_state.mute,
+            server_deaf: self.voice_state.deaf,
+            suppress: self.voice_state.suppress,
+            video: self.voice_state.self_video,
+            priority_speaker: false,
+            nick: self.nick,
+            ..Default::default()
+        }
+    }
+
+    fn into_update(self, channel_id: &str) -> VoiceUserPartial {
+        let user = self.into_voice_user(channel_id);
+        VoiceUserPartial {
+            user_id: user.user_id,
+            username: Some(user.username),
+            avatar_url: user.avatar_url.map_or(Field::Null, Field::Value),
+            channel_id: Field::Value(channel_id.to_string()),
+            deaf: Some(user.deaf),
+            mute: Some(user.mute),
+            streaming: Some(user.streaming),
+            speaking: None,
+            self_mute: Some(user.self_mute),
+            self_deaf: Some(user.self_deaf),
+            server_mute: Some(user.server_mute),
+            server_deaf: Some(user.server_deaf),
+            suppress: Some(user.suppress),
+            video: Some(user.video),
+            priority_speaker: None,
+            nick: user.nick.map_or(Field::Null, Field::Value),
+            ..Default::default()
+        }
+    }
+}
+
+/// Translates RPC events for one Discord connection into overlay events
+struct RpcSession<'a> {
+    session_id: String,
+    /// Channel whose events we are subscribed to
+    channel_id: Option<String>,
+    tx: &'a mpsc::Sender<SessionEvent>,
+}
+
+impl RpcSession<'_> {
+    async fn emit(&self, event: OverlayEvent) -> RpcResult<()> {
+        self.tx
+            .send(SessionEvent {
+                session_id: self.session_id.clone(),
+                event,
+            })
+            .await
+            .map_err(|_| "overlay is gone".into())
+    }
+
+    async fn on_event(&mut self, rpc: &mut RpcConnection, mut event: Value) -> RpcResult<()> {
+        let evt = event["evt"].as_str().unwrap_or_default().to_string();
+        let data = event["data"].take();
+
+        match evt.as_str() {
+            "VOICE_CHANNEL_SELECT" => self.on_channel_select(rpc, data).await?,
+            "VOICE_STATE_CREATE" | "VOICE_STATE_UPDATE" => {
+                let Some(channel_id) = &self.channel_id else {
+                    return Ok(());
+                };
+                let state: RpcVoiceState = serde_json::from_value(data)?;
+                self.emit(OverlayEvent::VoiceStateUpdate(state.into_update(channel_id))).await?;
+            }
+            "VOICE_STATE_DELETE" => {
+                let state: RpcVoiceState = serde_json::from_value(data)?;
+                self.emit(OverlayEvent::VoiceStateUpdate(VoiceUserPartial {
+                    user_id: state.user.id,
+                    channel_id: Field::Null,
+                    ..Default::default()
+                }))
+                .await?;
+            }
+            "SPEAKING_START" | "SPEAKING_STOP" => {
+                let Some(user_id) = data["user_id"].as_str() else {
+                    return Ok(());
+                };
+                self.emit(OverlayEvent::VoiceStateUpdate(VoiceUserPartial {
+                    user_id: user_id.to_string(),
+                    speaking: Some(evt == "SPEAKING_START"),
+                    ..Default::default()
+                }))
+                .await?;
+            }
+            other => debug!("Ignoring RPC event {}", other),
+        }
+
+        Ok(())
+    }
+
+    async fn on_channel_select(&mut self, rpc: &mut RpcConnection, data: Value) -> RpcResult<()> {
+        let selected = data["channel_id"].as_str().map(str::to_string);
+        if selected == self.channel_id {
+            return Ok(());
+        }
+
+        if selected.is_none() {
+            return self.on_channel(rpc, Value::Null).await;
+        }
+        let channel = rpc.call("GET_SELECTED_VOICE_CHANNEL", json!({}), None).await?;
+        self.on_channel(rpc, channel).await
+    }
+
+    /// Move our subscriptions to `channel` (null when the user left voice) and send the roster
+    async fn on_channel(&mut self, rpc: &mut RpcConnection, channel: Value) -> RpcResult<()> {
+        if let Some(old) = self.channel_id.take() {
+            for evt in CHANNEL_EVENTS {
+                rpc.call("UNSUBSCRIBE", json!({ "channel_id": old }), Some(evt)).await?;
+            }
+        }
+
+        if channel.is_null() {
+            info!("[{}] Left voice", self.session_id);
+            return self.emit(OverlayEvent::ChannelLeft).await;
+        }
+
+        let channel: RpcChannel = serde_json::from_value(channel)?;
+        for evt in CHANNEL_EVENTS {
+            rpc.call("SUBSCRIBE", json!({ "channel_id": channel.id }), Some(evt)).await?;
+        }
+
+        let users = channel
+            .voice_states
+            .into_iter()
+            .map(|state| state.into_voice_user(&channel.id))
+            .collect();
+        let channel_type = if channel.kind == STAGE_CHANNEL_TYPE {
+            ChannelType::Stage
+        } else {
+            ChannelType::Voice
+        };
+        self.channel_id = Some(channel.id);
+        self.emit(OverlayEvent::ChannelJoined(users, channel.name, channel_type)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncRead;
+    use tokio::net::{TcpListener, UnixListener};
+
+    async fn read_frame(stream: &mut UnixStream) -> (u32, Value) {
+        let mut header = [0u8; 8];
+        stream.read_exact(&mut header).await.unwrap();
+        let opcode = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let mut body = vec![0u8; length];
+        stream.read_exact(&mut body).await.unwrap();
+        (opcode, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn write_frame(stream: &mut UnixStream, opcode: u32, payload: Value) {
+        let body = serde_json::to_vec(&payload).unwrap();
+        stream.write_all(&opcode.to_le_bytes()).await.unwrap();
+        stream.write_all(&(body.len() as u32).to_le_bytes()).await.unwrap();
+        stream.write_all(&body).await.unwrap();
+    }
+
+    /// Answer the next command, checking it is `cmd`, and return its arguments
+    async fn reply(stream: &mut UnixStream, cmd: &str, data: Value) -> Value {
+        let (opcode, request) = read_frame(stream).await;
+        assert_eq!(opcode, OP_FRAME);
+        assert_eq!(request["cmd"], cmd);
+        let response = json!({ "cmd": cmd, "nonce": request["nonce"], "data": data });
+        write_frame(stream, OP_FRAME, response).await;
+        request["args"].clone()
+    }
+
+    /// Read one HTTP request without parsing more than needed
+    async fn read_request(stream: &mut (impl AsyncRead + Unpin)) -> String {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length: ").map(str::to_string))
+                    .and_then(|length| length.parse::<usize>().ok())
+                    .unwrap_or(0);
+                if body.len() >= length || n == 0 {
+                    return text;
+                }
+            }
+        }
+    }
+
+    /// Stand-in for the OAuth token exchange
+    async fn serve_token(listener: TcpListener) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let request = read_request(&mut stream).await;
+        assert!(request.contains(r#""code":"granted""#), "{}", request);
+
+        let body = r#"{"access_token":"stand-in-token"}"#;
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+    }
+
+    /// Stand-in for the Discord client: handshake, authorization, one channel, then quit
+    async fn serve_discord(listener: UnixListener) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        let (opcode, handshake) = read_frame(&mut stream).await;
+        assert_eq!(opcode, OP_HANDSHAKE);
+        assert_eq!(handshake["client_id"], "1234");
+        let ready = json!({ "cmd": "DISPATCH", "evt": "READY", "data": { "user": { "id": "42" } } });
+        write_frame(&mut stream, OP_FRAME, ready).await;
+
+        let authorize = reply(&mut stream, "AUTHORIZE", json!({ "code": "granted" })).await;
+        assert_eq!(authorize["scopes"], json!(["rpc"]));
+        let authenticate = reply(&mut stream, "AUTHENTICATE", json!({})).await;
+        assert_eq!(authenticate["access_token"], "stand-in-token");
+
+        reply(&mut stream, "SUBSCRIBE", json!({})).await;
+        let channel = json!({
+            "id": "100",
+            "name": "General",
+            "type": 2,
+            "voice_states": [{
+                "nick": null,
+                "voice_state": { "self_mute": true },
+                "user": { "id": "42", "username": "me", "global_name": null, "avatar": null }
+            }]
+        });
+        reply(&mut stream, "GET_SELECTED_VOICE_CHANNEL", channel).await;
+        for _ in CHANNEL_EVENTS {
+            let args = reply(&mut stream, "SUBSCRIBE", json!({})).await;
+            assert_eq!(args["channel_id"], "100");
+        }
+
+        let joined = json!({
+            "cmd": "DISPATCH",
+            "evt": "VOICE_STATE_CREATE",
+            "data": {
+                "nick": "Friend",
+                "voice_state": {},
+                "user": { "id": "7", "username": "friend", "global_name": null, "avatar": null }
+            }
+        });
+        write_frame(&mut stream, OP_FRAME, joined).await;
+        let speaking = json!({ "cmd": "DISPATCH", "evt": "SPEAKING_START", "data": { "user_id": "7" } });
+        write_frame(&mut stream, OP_FRAME, speaking).await;
+        // Dropping the stream is Discord quitting
+    }
+
+    #[tokio::test]
+    async fn session_against_stand_in_socket() {
+        let dir = std::env::temp_dir().join(format!("chotop-rpc-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let socket_path = dir.join("discord-ipc-0");
+
+        let discord = tokio::spawn(serve_discord(UnixListener::bind(&socket_path).unwrap()));
+        let token_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let token_url = format!("http://{}/token", token_listener.local_addr().unwrap());
+        let token_server = tokio::spawn(serve_token(token_listener));
+
+        let client = DiscordRpcClient {
+            socket_path: Some(socket_path.clone()),
+            client_id: "1234".to_string(),
+            token_url,
+            token_path: dir.join("discord-rpc-token"),
+        };
+        assert_eq!(client.find_socket(), Some(socket_path));
+
+        let (tx, mut rx) = mpsc::channel(16);
+        let client_task = tokio::spawn(client.run(tx, StatusHandle::default()));
+
+        let mut events = Vec::new();
+        while let Some(event) = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap() {
+            assert_eq!(event.session_id, "42");
+            let lost = matches!(event.event, OverlayEvent::SourceLost);
+            events.push(event.event);
+            if lost {
+                break;
+            }
+        }
+        client_task.abort();
+        discord.await.unwrap();
+        token_server.await.unwrap();
+
+        assert_eq!(events.len(), 4, "{:?}", events);
+        match &events[0] {
+            OverlayEvent::ChannelJoined(users, name, channel_type) => {
+                assert_eq!(name, "General");
+                assert_eq!(*channel_type, ChannelType::Voice);
+                assert_eq!(users.len(), 1);
+                assert!(users[0].self_mute);
+            }
+            other => panic!("expected ChannelJoined, got {:?}", other),
+        }
+        match &events[1] {
+            OverlayEvent::VoiceStateUpdate(update) => {
+                assert_eq!(update.user_id, "7");
+                assert_eq!(update.username.as_deref(), Some("friend"));
+                assert_eq!(update.nick, Field::Value("Friend".to_string()));
+                assert_eq!(update.channel_id, Field::Value("100".to_string()));
+            }
+            other => panic!("expected VoiceStateUpdate, got {:?}", other),
+        }
+        match &events[2] {
+            OverlayEvent::VoiceStateUpdate(update) => {
+                assert_eq!(update.user_id, "7");
+                assert_eq!(update.speaking, Some(true));
+                assert_eq!(update.channel_id, Field::Absent);
+            }
+            other => panic!("expected a speaking update, got {:?}", other),
+        }
+        assert!(matches!(events[3], OverlayEvent::SourceLost));
+
+        assert_eq!(fs::read_to_string(dir.join("discord-rpc-token")).unwrap(), "stand-in-token");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::{info, warn};
+
+use crate::config::{Config, EventSourceKind};
+use crate::discord_data::{PluginCommand, SessionEvent};
+use crate::discord_rpc::DiscordRpcSource;
+use crate::ipc::{OrbolaySource, ProtocolStats};
+use crate::recording::{FrameRecorder, ReplaySource};
+
+/// What an event source is doing, reported over the control socket
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", content = "detail", rename_all = "camelCase")]
+pub enum SourceStatus {
+    #[default]
+    Stopped,
+    Starting,
+    /// Producing events (e.g. "listening on 127.0.0.1:6888")
+    Running(String),
+    /// Healthy but waiting for its peer (e.g. Discord not running)
+    Waiting(String),
+    /// Gave up, the source needs a restart
+    Failed(String),
+}
+
+/// Status shared between a source's thread and whoever reports it
+#[derive(Debug, Clone, Default)]
+pub struct StatusHandle(Arc<Mutex<SourceStatus>>);
+
+impl StatusHandle {
+    pub fn set(&self, status: SourceStatus) {
+        if let Ok(mut current) = self.0.lock() {
+            *current = status;
+        }
+    }
+
+    pub fn get(&self) -> SourceStatus {
+        self.0.lock().map(|s| s.clone()).unwrap_or_default()
+    }
+}
+
+/// Status of one source, as sent to control clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceReport {
+    pub name: String,
+    pub status: SourceStatus,
+}
+
+/// Producer of overlay events (plugin server, Discord RPC, ...)
+pub trait EventSource {
+    /// Name used in logs and status reports
+    fn name(&self) -> &'static str;
+
+    /// Start producing events into `tx`, in the background
+    fn start(&mut self, tx: mpsc::Sender<SessionEvent>);
+
+    /// Stop producing events; the source can be started again.
+    /// Blocks until the source's thread is gone (see `SourceThread::stop`).
+    fn stop(&mut self);
+
+    /// Status the source keeps up to date, readable from any thread
+    fn status(&self) -> StatusHandle;
+
+    /// Take a config changed at runtime. Returns true if the source must be
+    /// restarted for it to apply; other settings wait for the next start.
+    fn reconfigure(&mut self, _config: &Config, _listen_addr: SocketAddr) -> bool {
+        false
+    }
+}
+
+/// Daemon-wide handles a source may need
+pub struct SourceContext {
+    pub listen_addr: SocketAddr,
+    /// Commands for plugins (voice controls), each addressed to one session
+    pub plugin_commands: broadcast::Sender<PluginCommand>,
+    pub protocol_stats: Arc<ProtocolStats>,
+    /// `--record`: where plugin sources write their raw frames
+    pub recorder: Option<FrameRecorder>,
+    /// `--replay` file and speed, which replaces every configured source
+    pub replay: Option<(PathBuf, f64)>,
+}
+
+/// Build the sources enabled in config
+pub fn from_config(config: &Config, context: &SourceContext) -> Vec<Box<dyn EventSource>> {
+    if let Some((path, speed)) = &context.replay {
+        info!("Replaying {:?} at {}x instead of running the configured sources", path, speed);
+        let replay = ReplaySource::new(path.clone(), *speed, context.protocol_stats.clone());
+        return vec![Box::new(replay)];
+    }
+
+    let mut sources: Vec<Box<dyn EventSource>> = Vec::new();
+
+    for (i, kind) in config.event_sources.iter().enumerate() {
+        if config.event_sources[..i].contains(kind) {
+            warn!("Event source {:?} is listed twice, ignoring the duplicate", kind);
+            continue;
+        }
+
+        match kind {
+            EventSourceKind::Orbolay => sources.push(Box::new(OrbolaySource::new(config, context))),
+            EventSourceKind::DiscordRpc => sources.push(Box::new(DiscordRpcSource::from_config(config))),
+        }
+    }
+
+    if sources.is_empty() {
+        warn!("No event sources enabled, the overlay will stay empty");
+    }
+    sources
+}
+
+/// Longest a stopping source may wait for its blocking tasks
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Background thread running one source's task on its own Tokio runtime
+pub struct SourceThread {
+    stop: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl SourceThread {
+    pub fn spawn<F>(name: &'static str, task: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (stop, stop_rx) = oneshot::channel();
+        let handle = std::thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
+            rt.block_on(async {
+                tokio::select! {
+                    _ = task => info!("Event source {} finished", name),
+                    _ = stop_rx => info!("Event source {} stopped", name),
+                }
+            });
+            // Cancels the source's connection tasks; only a stuck blocking call could hold this up
+            rt.shutdown_timeout(SHUTDOWN_TIMEOUT);
+        });
+
+        Self { stop, handle }
+    }
+
+    /// Stop the task and wait until its sockets are closed, so a restarted source can bind
+    /// them again. Called on the GTK thread: sources only run async tasks, which stop at
+    /// their next await, and the runtime shutdown is capped at `SHUTDOWN_TIMEOUT`.
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.handle.join();
+    }
+}
@@ -1,135 +1,975 @@
-use tokio::net::TcpListener;
-use tokio::sync::mpsc;
-use tokio_tungstenite::accept_async;
-use futures_util::StreamExt;
-use tracing::{info, warn, error};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::{broadcast, mpsc};
+use tokio_tungstenite::accept_hdr_async_with_config;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, WebSocketConfig};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use futures_util::{SinkExt, StreamExt};
+use tracing::{debug, info, warn, error};
 
+use crate::config::{Config, PluginTransport};
+use crate::event_source::{EventSource, SourceContext, SourceStatus, SourceThread, StatusHandle};
+use crate::pairing::PairingToken;
+use crate::recording::FrameRecorder;
 use crate::discord_data::{
-    GenericMessage, ChannelJoinedMessage, VoiceStateUpdateMessage,
-    ConfigMessage, MessageNotification, OverlayEvent,
+    Capability, HelloMessage, IncomingMessage, OutgoingMessage, OverlayEvent, PluginCommand,
+    ProtocolError, SessionEvent, DAEMON_CAPABILITIES, PROTOCOL_VERSION,
 };
 
-/// WebSocket server that receives voice state updates from OrbolayBridge plugin
+/// Counters for frames received from plugins, shared across connections
+#[derive(Debug, Default)]
+pub struct ProtocolStats {
+    frames: AtomicU64,
+    parse_errors: AtomicU64,
+    unknown_commands: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+/// Point-in-time copy of the protocol counters (reported over the control socket)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtocolStatsSnapshot {
+    pub frames: u64,
+    pub parse_errors: u64,
+    pub unknown_commands: u64,
+    pub last_error: Option<String>,
+}
+
+impl ProtocolStats {
+    fn record_error(&self, error: &ProtocolError) {
+        self.parse_errors.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut last_error) = self.last_error.lock() {
+            *last_error = Some(error.to_string());
+        }
+    }
+
+    pub fn snapshot(&self) -> ProtocolStatsSnapshot {
+        ProtocolStatsSnapshot {
+            frames: self.frames.load(Ordering::Relaxed),
+            parse_errors: self.parse_errors.load(Ordering::Relaxed),
+            unknown_commands: self.unknown_commands.load(Ordering::Relaxed),
+            last_error: self.last_error.lock().ok().and_then(|e| e.clone()),
+        }
+    }
+}
+
+/// Outcome of the HELLO exchange for one connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct Handshake {
+    /// Protocol version reported by the plugin (0 for plugins that never send HELLO)
+    pub peer_version: u32,
+    /// Capabilities supported by both sides
+    pub capabilities: Vec<Capability>,
+}
+
+impl Handshake {
+    /// Plugins predating the handshake only get the base protocol
+    fn legacy() -> Self {
+        Self {
+            peer_version: 0,
+            capabilities: Vec::new(),
+        }
+    }
+
+    fn negotiate(hello: &HelloMessage) -> Self {
+        if hello.protocol_version != PROTOCOL_VERSION {
+            warn!(
+                "Plugin speaks protocol v{} but daemon speaks v{}; running in degraded mode (base commands only). Update {} to restore all features.",
+                hello.protocol_version,
+                PROTOCOL_VERSION,
+                if hello.protocol_version < PROTOCOL_VERSION { "the plugin" } else { "chotop" },
+            );
+            return Self {
+                peer_version: hello.protocol_version,
+                capabilities: Vec::new(),
+            };
+        }
+
+        let capabilities: Vec<Capability> = hello
+            .capabilities
+            .iter()
+            .copied()
+            .filter(|c| DAEMON_CAPABILITIES.contains(c))
+            .collect();
+        info!("Handshake complete: protocol v{}, capabilities {:?}", hello.protocol_version, capabilities);
+
+        Self {
+            peer_version: hello.protocol_version,
+            capabilities,
+        }
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+}
+
+/// Limits applied to every plugin connection, so a web page in a local browser
+/// can't connect or flood the overlay
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerLimits {
+    pub allowed_origins: Vec<String>,
+    pub max_message_size: usize,
+    pub max_frame_size: usize,
+    pub max_messages_per_second: u32,
+    pub require_pairing: bool,
+    pub heartbeat_interval: Duration,
+    pub heartbeat_grace: Duration,
+}
+
+impl ServerLimits {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            allowed_origins: config.allowed_origins.clone(),
+            max_message_size: config.max_message_size,
+            max_frame_size: config.max_frame_size,
+            max_messages_per_second: config.max_messages_per_second,
+            require_pairing: config.require_pairing,
+            heartbeat_interval: Duration::from_secs(config.heartbeat_interval_secs.max(1)),
+            heartbeat_grace: Duration::from_secs(config.heartbeat_grace_secs.max(1)),
+        }
+    }
+
+    /// Browsers always send Origin, so a missing header means a non-browser client
+    /// (e.g. the node side of a desktop client)
+    fn origin_allowed(&self, origin: Option<&str>) -> bool {
+        match origin {
+            None => true,
+            Some(origin) => {
+                let origin = origin.trim_end_matches('/');
+                self.allowed_origins
+                    .iter()
+                    .any(|allowed| allowed.trim_end_matches('/').eq_ignore_ascii_case(origin))
+            }
+        }
+    }
+
+    fn websocket_config(&self) -> WebSocketConfig {
+        WebSocketConfig {
+            max_message_size: Some(self.max_message_size),
+            max_frame_size: Some(self.max_frame_size),
+            ..Default::default()
+        }
+    }
+}
+
+/// Token bucket allowing short bursts of twice the sustained rate
+struct RateLimiter {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    fn new(per_second: u32) -> Self {
+        let rate = per_second as f64;
+        Self {
+            rate,
+            capacity: rate * 2.0,
+            tokens: rate * 2.0,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn allow(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Open plugin connections per session id. A dropped connection only counts as a lost
+/// source if nothing else (typically the plugin reconnecting) carries the same session.
+#[derive(Debug, Clone, Default)]
+struct LiveSessions(Arc<Mutex<HashMap<String, usize>>>);
+
+impl LiveSessions {
+    fn attach(&self, session_id: &str) {
+        if let Ok(mut live) = self.0.lock() {
+            *live.entry(session_id.to_string()).or_default() += 1;
+        }
+    }
+
+    fn detach(&self, session_id: &str) {
+        if let Ok(mut live) = self.0.lock() {
+            if let Some(count) = live.get_mut(session_id) {
+                *count -= 1;
+                if *count == 0 {
+                    live.remove(session_id);
+                }
+            }
+        }
+    }
+
+    fn is_live(&self, session_id: &str) -> bool {
+        self.0.lock().map(|live| live.contains_key(session_id)).unwrap_or(false)
+    }
+}
+
+/// Unix socket serving the plugin protocol
+fn plugin_socket_path() -> PathBuf {
+    Config::runtime_dir().join("chotop-plugin.sock")
+}
+
+/// The OrbolayBridge plugin server as an event source
+pub struct OrbolaySource {
+    listen_addr: SocketAddr,
+    recorder: Option<FrameRecorder>,
+    config: Config,
+    stats: Arc<ProtocolStats>,
+    commands: broadcast::Sender<PluginCommand>,
+    status: StatusHandle,
+    thread: Option<SourceThread>,
+    /// Transports the running server was started with; `stop` cleans up after these,
+    /// as `config` may already hold the ones to restart with
+    serving: Vec<PluginTransport>,
+}
+
+impl OrbolaySource {
+    pub fn new(config: &Config, context: &SourceContext) -> Self {
+        Self {
+            listen_addr: context.listen_addr,
+            recorder: context.recorder.clone(),
+            config: config.clone(),
+            stats: context.protocol_stats.clone(),
+            commands: context.plugin_commands.clone(),
+            status: StatusHandle::default(),
+            thread: None,
+            serving: Vec::new(),
+        }
+    }
+}
+
+impl EventSource for OrbolaySource {
+    fn name(&self) -> &'static str {
+        "orbolay"
+    }
+
+    fn start(&mut self, tx: mpsc::Sender<SessionEvent>) {
+        if self.thread.is_some() {
+            return;
+        }
+
+        let mut server = WebSocketServer::new(ServerLimits::from_config(&self.config), self.stats.clone())
+            .with_recorder(self.recorder.clone());
+        if self.config.plugin_transports.contains(&PluginTransport::Tcp) {
+            server = server.with_tcp(self.listen_addr, self.config.port_fallback_range);
+        }
+        if self.config.plugin_transports.contains(&PluginTransport::Unix) {
+            server = server.with_unix_socket(plugin_socket_path());
+        }
+
+        self.status.set(SourceStatus::Starting);
+        let task = server.run(tx, self.commands.clone(), self.status.clone());
+        self.thread = Some(SourceThread::spawn(self.name(), task));
+        self.serving = self.config.plugin_transports.clone();
+    }
+
+    fn stop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            thread.stop();
+            if self.serving.contains(&PluginTransport::Unix) {
+                let _ = std::fs::remove_file(plugin_socket_path());
+            }
+            // Nothing listens on the advertised port anymore
+            if self.serving.contains(&PluginTransport::Tcp) {
+                let _ = std::fs::remove_file(Config::port_file_path());
+            }
+            self.serving.clear();
+        }
+        self.status.set(SourceStatus::Stopped);
+    }
+
+    fn status(&self) -> StatusHandle {
+        self.status.clone()
+    }
+
+    /// Restart when the address, transports or connection limits changed; a running
+    /// server keeps the limits (pairing, origins, sizes, rates, heartbeat) it was started with
+    fn reconfigure(&mut self, config: &Config, listen_addr: SocketAddr) -> bool {
+        let restart = listen_addr != self.listen_addr
+            || config.plugin_transports != self.config.plugin_transports
+            || config.port_fallback_range != self.config.port_fallback_range
+            || ServerLimits::from_config(config) != ServerLimits::from_config(&self.config);
+
+        self.listen_addr = listen_addr;
+        self.config = config.clone();
+        restart
+    }
+}
+
+/// Attempts on the configured port before falling back (a restarting daemon may still hold it)
+const BIND_ATTEMPTS: u32 = 3;
+
+/// Connection numbers for the whole process, so a restarted server can't hand out an id
+/// the session manager still knows from a connection of the previous one
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// WebSocket server that receives voice state updates from OrbolayBridge plugin.
+/// The same protocol is served over TCP and/or a Unix socket.
 pub struct WebSocketServer {
-    port: u16,
+    tcp: Option<(SocketAddr, u16)>,
+    unix_socket: Option<PathBuf>,
+    limits: Arc<ServerLimits>,
+    stats: Arc<ProtocolStats>,
+    live_sessions: LiveSessions,
+    recorder: Option<FrameRecorder>,
 }
 
 impl WebSocketServer {
-    pub fn new(port: u16) -> Self {
-        Self { port }
+    pub fn new(limits: ServerLimits, stats: Arc<ProtocolStats>) -> Self {
+        Self {
+            tcp: None,
+            unix_socket: None,
+            limits: Arc::new(limits),
+            stats,
+            live_sessions: LiveSessions::default(),
+            recorder: None,
+        }
     }
 
-    /// Start the WebSocket server and send events through the channel
-    pub async fn run(self, tx: mpsc::Sender<OverlayEvent>) {
-        let addr = format!("127.0.0.1:{}", self.port);
+    /// Listen on `addr`, trying up to `port_fallback_range` following ports if it is taken
+    pub fn with_tcp(mut self, addr: SocketAddr, port_fallback_range: u16) -> Self {
+        self.tcp = Some((addr, port_fallback_range));
+        self
+    }
+
+    /// Listen on a Unix socket at `path`
+    pub fn with_unix_socket(mut self, path: PathBuf) -> Self {
+        self.unix_socket = Some(path);
+        self
+    }
+
+    /// Write every incoming frame to `recorder`
+    pub fn with_recorder(mut self, recorder: Option<FrameRecorder>) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Start the WebSocket server and send events through the channel.
+    /// Commands published on `commands` are forwarded to the plugin of their session.
+    pub async fn run(
+        self,
+        tx: mpsc::Sender<SessionEvent>,
+        commands: broadcast::Sender<PluginCommand>,
+        status: StatusHandle,
+    ) {
+        let tcp_listener = match self.tcp {
+            Some((addr, port_fallback_range)) => Self::bind_tcp(addr, port_fallback_range).await,
+            None => None,
+        };
+        let unix_listener = self.unix_socket.as_deref().and_then(Self::bind_unix);
+
+        if tcp_listener.is_none() && unix_listener.is_none() {
+            error!("No plugin transport is listening, plugin connections are disabled");
+            status.set(SourceStatus::Failed("no plugin transport could be bound".to_string()));
+            return;
+        }
+
+        let mut listening = Vec::new();
+        if let Some(addr) = tcp_listener.as_ref().and_then(|l| l.local_addr().ok()) {
+            listening.push(addr.to_string());
+        }
+        if let (Some(_), Some(path)) = (&unix_listener, &self.unix_socket) {
+            listening.push(path.display().to_string());
+        }
+        status.set(SourceStatus::Running(format!("listening on {}", listening.join(", "))));
+
+        let tcp = async {
+            let Some(listener) = tcp_listener else { return };
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        let local_port = stream.local_addr().ok().map(|addr| addr.port());
+                        self.spawn_connection(stream, peer.to_string(), local_port, &tx, &commands);
+                    }
+                    Err(e) => error!("Failed to accept connection: {}", e),
+                }
+            }
+        };
 
-        let listener = match TcpListener::bind(&addr).await {
-            Ok(l) => {
-                info!("WebSocket server listening on {}", addr);
-                l
+        let unix = async {
+            let Some(listener) = unix_listener else { return };
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        self.spawn_connection(stream, "unix socket".to_string(), None, &tx, &commands);
+                    }
+                    Err(e) => error!("Failed to accept connection: {}", e),
+                }
             }
+        };
+
+        tokio::join!(tcp, unix);
+    }
+
+    /// Run the shared protocol pipeline for a freshly accepted stream, whatever its transport
+    fn spawn_connection<S>(
+        &self,
+        stream: S,
+        peer: String,
+        local_port: Option<u16>,
+        tx: &mpsc::Sender<SessionEvent>,
+        commands: &broadcast::Sender<PluginCommand>,
+    ) where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        info!("New connection from: {}", peer);
+        // Re-read per connection so a token regenerated from chotop-config applies immediately
+        let token = match self.limits.require_pairing.then(PairingToken::load_or_create).transpose() {
+            Ok(token) => token,
             Err(e) => {
-                error!("Failed to bind WebSocket server: {}", e);
+                error!("Rejecting connection from {}: no pairing token available: {}", peer, e);
                 return;
             }
         };
+        let tx = tx.clone();
+        let commands_rx = commands.subscribe();
+        let id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+        let mut connection =
+            Connection::new(id, token, self.stats.clone(), self.live_sessions.clone());
+        connection.local_port = local_port;
+        connection.recorder = self.recorder.clone();
+        let limits = self.limits.clone();
+        let live_sessions = self.live_sessions.clone();
 
-        loop {
-            match listener.accept().await {
-                Ok((stream, peer)) => {
-                    info!("New connection from: {}", peer);
-                    let tx_clone = tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) =
+                handle_connection(stream, &peer, limits.clone(), tx.clone(), commands_rx, &mut connection).await
+            {
+                warn!("Connection error: {}", e);
+            }
+            info!("Connection closed: {}", peer);
 
-                    tokio::spawn(async move {
-                        if let Err(e) = handle_connection(stream, tx_clone).await {
-                            warn!("Connection error: {}", e);
-                        }
-                        info!("Connection closed: {}", peer);
-                    });
+            let session_id = connection.session_id.clone();
+            let established = connection.handshake.is_some();
+            let closed_cleanly = connection.closed_cleanly;
+            let remaining = limits.heartbeat_grace.saturating_sub(connection.last_seen.elapsed());
+            drop(connection);
+
+            // Rejected before its first valid frame (origin, pairing): it never reported anything
+            if !established {
+                return;
+            }
+
+            // A plugin that said goodbye (quit, reload, closed by us) takes its roster with it,
+            // unless it already reconnected under the same session
+            if closed_cleanly {
+                if !live_sessions.is_live(&session_id) {
+                    info!("Plugin session {} closed", session_id);
+                    let _ = tx
+                        .send(SessionEvent {
+                            session_id,
+                            event: OverlayEvent::SessionClosed,
+                        })
+                        .await;
+                }
+                return;
+            }
+
+            // The plugin vanished without a close frame (crash, suspend, network drop).
+            // Give it the rest of the grace period to reconnect before calling it lost.
+            tokio::time::sleep(remaining).await;
+
+            if !live_sessions.is_live(&session_id) {
+                warn!("Plugin session {} lost", session_id);
+                let _ = tx
+                    .send(SessionEvent {
+                        session_id,
+                        event: OverlayEvent::SourceLost,
+                    })
+                    .await;
+            }
+        });
+    }
+
+    /// Bind the configured address, retrying while a previous daemon releases it,
+    /// then trying the next `port_fallback_range` ports
+    async fn bind_tcp(addr: SocketAddr, port_fallback_range: u16) -> Option<TcpListener> {
+        let listener = Self::bind_tcp_with_fallback(addr, port_fallback_range).await?;
+
+        if let Ok(addr) = listener.local_addr() {
+            info!("WebSocket server listening on {}", addr);
+
+            // Tell chotop-config which port we really got
+            if let Err(e) = std::fs::write(Config::port_file_path(), addr.port().to_string()) {
+                warn!("Failed to write port file: {}", e);
+            }
+        }
+
+        Some(listener)
+    }
+
+    async fn bind_tcp_with_fallback(addr: SocketAddr, port_fallback_range: u16) -> Option<TcpListener> {
+        for attempt in 1..=BIND_ATTEMPTS {
+            match TcpListener::bind(addr).await {
+                Ok(listener) => return Some(listener),
+                Err(e) if e.kind() == std::io::ErrorKind::AddrInUse && attempt < BIND_ATTEMPTS => {
+                    warn!(
+                        "Port {} is in use (attempt {}/{}), retrying in 1s",
+                        addr.port(), attempt, BIND_ATTEMPTS
+                    );
+                    tokio::time::sleep(Duration::from_secs(1)).await;
                 }
                 Err(e) => {
-                    error!("Failed to accept connection: {}", e);
+                    error!("Failed to bind WebSocket server on {}: {}", addr, e);
+                    break;
                 }
             }
         }
+
+        for offset in 1..=port_fallback_range {
+            let Some(port) = addr.port().checked_add(offset) else {
+                break;
+            };
+            let fallback = SocketAddr::new(addr.ip(), port);
+
+            match TcpListener::bind(fallback).await {
+                Ok(listener) => {
+                    warn!(
+                        "Using fallback port {} instead of {}; set the plugin's port to match",
+                        port,
+                        addr.port()
+                    );
+                    return Some(listener);
+                }
+                Err(e) => debug!("Fallback port {} unavailable: {}", port, e),
+            }
+        }
+
+        error!("No usable WebSocket port, TCP plugin connections are disabled");
+        None
+    }
+
+    /// Bind the plugin socket, replacing a stale file left by a crashed daemon
+    fn bind_unix(path: &Path) -> Option<UnixListener> {
+        if path.exists() {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                error!("Another daemon is already listening on {:?}", path);
+                return None;
+            }
+            let _ = std::fs::remove_file(path);
+        }
+
+        match Self::bind_private(path) {
+            Ok(listener) => {
+                info!("WebSocket server listening on {:?}", path);
+                Some(listener)
+            }
+            Err(e) => {
+                error!("Failed to bind plugin socket {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    /// Filesystem permissions are the access control for this transport, so the socket is
+    /// created in a private directory and only moved to `path` once it is 0600
+    fn bind_private(path: &Path) -> std::io::Result<UnixListener> {
+        let staging = path.with_extension(format!("{}.tmp", std::process::id()));
+        let _ = std::fs::remove_dir_all(&staging);
+        std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+
+        let staged = staging.join("socket");
+        let bound = UnixListener::bind(&staged).and_then(|listener| {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+            std::fs::rename(&staged, path)?;
+            Ok(listener)
+        });
+
+        let _ = std::fs::remove_file(&staged);
+        let _ = std::fs::remove_dir(&staging);
+        bound
     }
 }
 
-async fn handle_connection(
-    stream: tokio::net::TcpStream,
-    tx: mpsc::Sender<OverlayEvent>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let ws_stream = accept_async(stream).await?;
-    let (_, mut read) = ws_stream.split();
+async fn handle_connection<S>(
+    stream: S,
+    peer: &str,
+    limits: Arc<ServerLimits>,
+    tx: mpsc::Sender<SessionEvent>,
+    mut commands: broadcast::Receiver<PluginCommand>,
+    connection: &mut Connection,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let origin_limits = limits.clone();
+    let origin_peer = peer.to_string();
+    // The callback signature is fixed by tungstenite
+    #[allow(clippy::result_large_err)]
+    let check_origin = move |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+        let origin = request.headers().get("origin").map(|o| o.to_str().unwrap_or("<invalid>"));
+        if origin_limits.origin_allowed(origin) {
+            return Ok(response);
+        }
 
-    while let Some(msg) = read.next().await {
-        match msg {
-            Ok(message) => {
-                if message.is_text() {
-                    let text = message.to_text()?;
+        warn!("Rejected connection from {}: origin {:?} is not in allowed_origins", origin_peer, origin);
+        let mut rejection = ErrorResponse::new(Some("Origin not allowed".to_string()));
+        *rejection.status_mut() = StatusCode::FORBIDDEN;
+        Err(rejection)
+    };
 
-                    // First parse to get the command type
-                    match serde_json::from_str::<GenericMessage>(text) {
-                        Ok(generic) => {
-                            let event = match generic.cmd.as_str() {
-                                "REGISTER_CONFIG" => {
-                                    serde_json::from_str::<ConfigMessage>(text)
-                                        .ok()
-                                        .map(OverlayEvent::ConfigReceived)
-                                }
-                                "CHANNEL_JOINED" => {
-                                    serde_json::from_str::<ChannelJoinedMessage>(text)
-                                        .ok()
-                                        .map(|m| OverlayEvent::ChannelJoined(
-                                            m.states,
-                                            m.channel_name.unwrap_or_else(|| "Voice Channel".to_string())
-                                        ))
-                                }
-                                "CHANNEL_LEFT" => {
-                                    Some(OverlayEvent::ChannelLeft)
-                                }
-                                "VOICE_STATE_UPDATE" => {
-                                    serde_json::from_str::<VoiceStateUpdateMessage>(text)
-                                        .ok()
-                                        .map(|m| OverlayEvent::VoiceStateUpdate(m.state))
-                                }
-                                "MESSAGE_NOTIFICATION" => {
-                                    serde_json::from_str::<MessageNotification>(text)
-                                        .ok()
-                                        .map(|m| OverlayEvent::MessageNotification(m.message))
-                                }
-                                _ => {
-                                    warn!("Unknown command: {}", generic.cmd);
-                                    None
-                                }
+    let ws_stream =
+        accept_hdr_async_with_config(stream, check_origin, Some(limits.websocket_config())).await?;
+    let (mut write, mut read) = ws_stream.split();
+    let mut rate_limiter = RateLimiter::new(limits.max_messages_per_second);
+    let mut heartbeat = tokio::time::interval_at(
+        tokio::time::Instant::now() + limits.heartbeat_interval,
+        limits.heartbeat_interval,
+    );
+
+    // Announce ourselves; plugins predating the handshake just ignore this
+    let hello = OutgoingMessage::Hello(HelloMessage {
+        protocol_version: PROTOCOL_VERSION,
+        capabilities: DAEMON_CAPABILITIES.to_vec(),
+        token: None,
+    });
+    write.send(Message::Text(serde_json::to_string(&hello)?)).await?;
+
+    loop {
+        tokio::select! {
+            msg = read.next() => {
+                let Some(msg) = msg else { break };
+
+                match msg {
+                    Ok(message) => {
+                        // Any frame, pongs included, proves the plugin is alive
+                        connection.last_seen = Instant::now();
+
+                        if !message.is_close() && !rate_limiter.allow() {
+                            warn!(
+                                "Closing connection from {}: more than {} messages per second",
+                                peer, limits.max_messages_per_second
+                            );
+                            let close = CloseFrame {
+                                code: CloseCode::Policy,
+                                reason: "Rate limit exceeded".into(),
                             };
+                            let _ = write.send(Message::Close(Some(close))).await;
+                            connection.closed_cleanly = true;
+                            break;
+                        }
 
-                            if let Some(evt) = event {
-                                if let Err(e) = tx.send(evt).await {
-                                    error!("Failed to send event: {}", e);
+                        if message.is_text() {
+                            let text = message.to_text()?;
+
+                            let events = match connection.handle_frame(text) {
+                                Ok(events) => events,
+                                Err(reason) => {
+                                    warn!("Closing connection from {}: {}", peer, reason);
+                                    let close = CloseFrame {
+                                        code: CloseCode::Policy,
+                                        reason: reason.into(),
+                                    };
+                                    let _ = write.send(Message::Close(Some(close))).await;
+                                    connection.closed_cleanly = true;
                                     break;
                                 }
+                            };
+
+                            for event in events {
+                                let evt = SessionEvent {
+                                    session_id: connection.session_id.clone(),
+                                    event,
+                                };
+                                if tx.send(evt).await.is_err() {
+                                    error!("Failed to send event: overlay is gone");
+                                    return Ok(());
+                                }
+                            }
+
+                            for reply in connection.take_replies() {
+                                write.send(Message::Text(serde_json::to_string(&reply)?)).await?;
                             }
+                        } else if message.is_close() {
+                            connection.closed_cleanly = true;
+                            break;
                         }
-                        Err(e) => {
-                            warn!("Failed to parse message: {} - {}", e, text);
+                    }
+                    Err(e @ WsError::Capacity(_)) => {
+                        warn!("Closing connection from {}: {} (see max_message_size / max_frame_size)", peer, e);
+                        break;
+                    }
+                    Err(e) => {
+                        warn!("WebSocket error: {}", e);
+                        break;
+                    }
+                }
+            }
+            command = commands.recv() => {
+                match command {
+                    Ok(command) => {
+                        if command.session_id != connection.session_id {
+                            continue;
+                        }
+                        if !connection.supports(Capability::BidirectionalControl) {
+                            warn!("Plugin did not negotiate voice control, dropping {:?}", command.message);
+                            continue;
                         }
+                        info!("Sending command to plugin: {:?}", command.message);
+                        let json = serde_json::to_string(&command.message)?;
+                        write.send(Message::Text(json)).await?;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Dropped {} plugin commands (connection too slow)", skipped);
                     }
-                } else if message.is_close() {
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+            _ = heartbeat.tick() => {
+                if connection.last_seen.elapsed() >= limits.heartbeat_grace {
+                    warn!(
+                        "No traffic from {} for {}s, assuming the plugin is gone",
+                        peer,
+                        limits.heartbeat_grace.as_secs()
+                    );
                     break;
                 }
+                write.send(Message::Ping(Vec::new())).await?;
             }
+        }
+    }
+
+    // No CHANNEL_LEFT from here: `spawn_connection` clears the session after a clean close,
+    // and keeps it for a reconnecting plugin after a dropped one
+    info!("WebSocket connection closed");
+    Ok(())
+}
+
+/// Protocol state for a single plugin connection
+pub struct Connection {
+    /// Per-daemon connection number
+    id: u64,
+    /// Discord user id once REGISTER_CONFIG arrives, a per-connection id before that
+    session_id: String,
+    handshake: Option<Handshake>,
+    /// Token the first frame must carry, `None` once paired (or when pairing is disabled)
+    expected_token: Option<String>,
+    registered: bool,
+    snapshot_requested: bool,
+    /// TCP port this connection came in on, compared with the port the plugin is configured for
+    local_port: Option<u16>,
+    /// Replies to send to the plugin after the current frame
+    outbox: Vec<OutgoingMessage>,
+    /// Last time any frame arrived, checked against the heartbeat grace period
+    last_seen: Instant,
+    /// The connection ended with a close frame, so the plugin didn't just vanish
+    closed_cleanly: bool,
+    stats: Arc<ProtocolStats>,
+    live_sessions: LiveSessions,
+    /// Writes each incoming frame when running with `--record`
+    recorder: Option<FrameRecorder>,
+}
+
+impl Connection {
+    fn new(
+        id: u64,
+        expected_token: Option<String>,
+        stats: Arc<ProtocolStats>,
+        live_sessions: LiveSessions,
+    ) -> Self {
+        let session_id = format!("connection-{}", id);
+        live_sessions.attach(&session_id);
+
+        Self {
+            id,
+            session_id,
+            handshake: None,
+            expected_token,
+            registered: false,
+            snapshot_requested: false,
+            local_port: None,
+            outbox: Vec::new(),
+            last_seen: Instant::now(),
+            closed_cleanly: false,
+            stats,
+            live_sessions,
+            recorder: None,
+        }
+    }
+
+    /// Connection fed from a recording: no pairing, no other connection shares its sessions
+    pub fn replay(id: u64, stats: Arc<ProtocolStats>) -> Self {
+        Self::new(id, None, stats, LiveSessions::default())
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Replies queued for the plugin by the frames handled so far
+    pub fn take_replies(&mut self) -> Vec<OutgoingMessage> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    fn supports(&self, capability: Capability) -> bool {
+        self.handshake.as_ref().is_some_and(|h| h.supports(capability))
+    }
+
+    /// Parse an incoming text frame into overlay events.
+    /// Returns the reason when the connection must be closed.
+    pub fn handle_frame(&mut self, text: &str) -> Result<Vec<OverlayEvent>, &'static str> {
+        self.stats.frames.fetch_add(1, Ordering::Relaxed);
+        if let Some(recorder) = &self.recorder {
+            recorder.record(self.id, text);
+        }
+
+        let message = match IncomingMessage::parse(text) {
+            Ok(message) => message,
             Err(e) => {
-                warn!("WebSocket error: {}", e);
-                break;
+                self.stats.record_error(&e);
+                // Not the frame itself: a pairing frame carries the token
+                warn!("Dropping frame: {} ({} bytes)", e, text.len());
+                if self.expected_token.is_some() {
+                    return Err("first frame is not a valid pairing frame");
+                }
+                return Ok(Vec::new());
+            }
+        };
+
+        // The first frame must carry the pairing token, in HELLO or REGISTER_CONFIG
+        if let Some(expected) = self.expected_token.take() {
+            let presented = match &message {
+                IncomingMessage::Hello(hello) => hello.token.as_deref(),
+                IncomingMessage::RegisterConfig(config) => config.token.as_deref(),
+                _ => None,
+            };
+            match presented {
+                Some(token) if token_matches(&expected, token) => {
+                    info!("Connection {} paired", self.session_id);
+                }
+                Some(_) => return Err("wrong pairing token"),
+                None => return Err("missing pairing token"),
             }
         }
+
+        let mut events = Vec::new();
+
+        if let IncomingMessage::Hello(hello) = &message {
+            self.handshake = Some(Handshake::negotiate(hello));
+            self.request_snapshot(&mut events);
+            return Ok(events);
+        }
+
+        if self.handshake.is_none() {
+            warn!("Plugin did not send HELLO, assuming legacy protocol (base commands only)");
+            self.handshake = Some(Handshake::legacy());
+        }
+
+        match message {
+            IncomingMessage::Hello(_) => {}
+            IncomingMessage::RegisterConfig(m) => {
+                if let Some(user_id) = m.user_id.as_ref().filter(|id| !id.is_empty()) {
+                    if *user_id != self.session_id {
+                        info!("Connection {} registered as session {}", self.session_id, user_id);
+                        self.live_sessions.detach(&self.session_id);
+                        self.live_sessions.attach(user_id);
+                        let previous = std::mem::replace(&mut self.session_id, user_id.clone());
+                        events.push(OverlayEvent::SessionRenamed(previous));
+                    }
+                }
+                if let (Some(plugin_port), Some(local_port)) = (m.port, self.local_port) {
+                    if plugin_port != local_port {
+                        warn!(
+                            "Plugin is configured for port {} but connected on {}; update one of them",
+                            plugin_port, local_port
+                        );
+                    }
+                }
+                self.registered = true;
+                events.push(OverlayEvent::ConfigReceived(m));
+                self.request_snapshot(&mut events);
+            }
+            IncomingMessage::ChannelJoined(m) => events.push(OverlayEvent::ChannelJoined(
+                m.states,
+                m.channel_name.unwrap_or_else(|| "Voice Channel".to_string()),
+                m.channel_type,
+            )),
+            IncomingMessage::ChannelLeft => events.push(OverlayEvent::ChannelLeft),
+            IncomingMessage::VoiceStateUpdate(m) => events.push(OverlayEvent::VoiceStateUpdate(m.state)),
+            IncomingMessage::VoiceSnapshot(m) => events.push(OverlayEvent::VoiceSnapshot(m)),
+            IncomingMessage::MessageNotification(m) => {
+                events.push(OverlayEvent::MessageNotification(m.message))
+            }
+            IncomingMessage::ChannelMessage(m) => events.push(OverlayEvent::ChannelMessage(m.message)),
+            IncomingMessage::Unknown(cmd) => {
+                self.stats.unknown_commands.fetch_add(1, Ordering::Relaxed);
+                warn!("Unknown command: {}", cmd);
+            }
+        }
+
+        Ok(events)
     }
 
-    // DON'T send channel left on WebSocket disconnect!
-    // The plugin will send CHANNEL_LEFT if the user actually leaves the voice channel.
-    // A WebSocket disconnect is just a technical disconnection (daemon restart, etc.)
-    // and we want to keep the overlay state for seamless reconnection.
-    info!("WebSocket connection closed, waiting for reconnection...");
-    Ok(())
+    /// Ask for a full snapshot once the plugin is identified and supports it,
+    /// so state kept across a reconnect gets reconciled
+    fn request_snapshot(&mut self, events: &mut Vec<OverlayEvent>) {
+        if self.snapshot_requested || !self.registered || !self.supports(Capability::VoiceSnapshot) {
+            return;
+        }
+
+        info!("Requesting voice snapshot for session {}", self.session_id);
+        self.snapshot_requested = true;
+        self.outbox.push(OutgoingMessage::RequestSnapshot);
+        events.push(OverlayEvent::SnapshotRequested);
+    }
+}
+
+/// Compare in constant time so the pairing token can't be guessed byte by byte
+fn token_matches(expected: &str, presented: &str) -> bool {
+    expected.len() == presented.len()
+        && expected
+            .bytes()
+            .zip(presented.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.live_sessions.detach(&self.session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn unix_socket_is_private() {
+        let dir = std::env::temp_dir().join(format!("chotop-ipc-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("plugin.sock");
+
+        let listener = WebSocketServer::bind_unix(&path).expect("bind");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // Only the socket is left behind, not the staging directory
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        // The listener still answers on the path it was moved to
+        assert!(std::os::unix::net::UnixStream::connect(&path).is_ok());
+
+        drop(listener);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
@@ -1,34 +1,60 @@
 mod avatar_cache;
+mod chat_window;
+mod cli;
 mod config;
 mod control_ipc;
+mod control_server;
 mod control_window;
 mod discord_data;
+mod discord_rpc;
+mod event_source;
 mod ipc;
 mod notification_window;
 mod overlay_window;
+mod pairing;
+mod recording;
+mod refresh;
 mod renderer;
+mod session;
 
 use std::cell::RefCell;
 use std::path::PathBuf;
 use std::rc::Rc;
+use std::sync::Arc;
 
 use gtk4::prelude::*;
 use gtk4::{glib, Application, ApplicationWindow};
-use tokio::sync::mpsc;
-use tracing::{info, Level};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{info, warn, Level};
 use tracing_subscriber::FmtSubscriber;
 
 use avatar_cache::AvatarCache;
-use config::Config;
-use control_ipc::{ControlCommand, ControlIpcServer};
-use discord_data::OverlayEvent;
-use ipc::WebSocketServer;
+use chat_window::ChatWindow;
+use cli::CliArgs;
+use config::{Config, SettingsSource};
+use control_ipc::{ControlCommand, ControlResponse};
+use control_server::{ControlIpcServer, ControlRequest};
+use discord_data::{ConfigMessage, OutgoingMessage, OverlayEvent, PluginCommand, SessionEvent};
+use event_source::{EventSource, SourceContext, StatusHandle};
+use ipc::ProtocolStats;
 use notification_window::NotificationWindow;
-use overlay_window::{load_css, setup_layer_shell};
+use overlay_window::{anchor_window, load_css, reload_css, set_click_through, setup_layer_shell};
+use recording::FrameRecorder;
+use refresh::OverlayRefresh;
 use renderer::{AvatarRequest, OverlayRenderer};
+use session::SessionManager;
 
 const APP_ID: &str = "com.discord.overlay";
-const WEBSOCKET_PORT: u16 = 6888; // Orbolay default port
+
+/// Overlay events buffered between the sources and the GTK thread
+const EVENT_QUEUE_SIZE: usize = 1024;
+
+/// Events applied per main loop iteration before yielding to GTK
+const MAX_EVENTS_PER_BATCH: usize = 256;
+
+/// Signals that quit the daemon through GTK, so the event sources clean up after themselves
+const SIGINT: i32 = 2;
+const SIGTERM: i32 = 15;
 
 fn main() -> glib::ExitCode {
     // Initialize logging
@@ -37,8 +63,19 @@ fn main() -> glib::ExitCode {
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("Failed to set subscriber");
 
+    let cli = match CliArgs::parse() {
+        Ok(cli) if cli.help => {
+            println!("{}", cli::USAGE);
+            return glib::ExitCode::SUCCESS;
+        }
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            return glib::ExitCode::FAILURE;
+        }
+    };
+
     info!("Starting Discord Overlay Daemon (Orbolay compatible)");
-    info!("Listening on port {}", WEBSOCKET_PORT);
 
     // Create GTK application (non-unique to allow multiple instances during dev)
     let app = Application::builder()
@@ -46,17 +83,27 @@ fn main() -> glib::ExitCode {
         .flags(gtk4::gio::ApplicationFlags::NON_UNIQUE)
         .build();
 
-    app.connect_activate(build_ui);
+    app.connect_activate(move |app| build_ui(app, &cli));
+
+    for signal in [SIGINT, SIGTERM] {
+        let app = app.clone();
+        glib::unix_signal_add_local(signal, move || {
+            info!("Signal {} received, quitting", signal);
+            app.quit();
+            glib::ControlFlow::Break
+        });
+    }
 
-    app.run()
+    // Our flags are already parsed, don't let GTK reject them
+    app.run_with_args::<&str>(&[])
 }
 
-fn build_ui(app: &Application) {
+fn build_ui(app: &Application, cli: &CliArgs) {
     // Load config
     let config = Config::load();
 
-    // Load CSS styles
-    load_css(&config);
+    // Load CSS styles, kept to be replaced on config updates
+    let css_provider = load_css(&config);
 
     // Create the main window
     let window = ApplicationWindow::builder()
@@ -73,11 +120,20 @@ fn build_ui(app: &Application) {
     let renderer = Rc::new(RefCell::new(OverlayRenderer::new()));
     window.set_child(Some(renderer.borrow().widget()));
 
+    // Per-client voice state, the renderer shows the session(s) picked by config
+    let sessions = Rc::new(RefCell::new(SessionManager::new(
+        config.session_mode,
+        config.pinned_session.clone(),
+    )));
+
     // Create notification window (separate window for messages)
     let notification_window = Rc::new(RefCell::new(NotificationWindow::new(app, &config)));
 
+    // Chat window for the voice channel's text chat, stays hidden while `chat_enabled` is off
+    let chat_window = Rc::new(RefCell::new(ChatWindow::new(app, &config)));
+
     // Create channel for overlay events
-    let (event_tx, mut event_rx) = mpsc::channel::<OverlayEvent>(100);
+    let (event_tx, mut event_rx) = mpsc::channel::<SessionEvent>(EVENT_QUEUE_SIZE);
 
     // Create channel for avatar requests
     let (avatar_tx, mut avatar_rx) = mpsc::channel::<AvatarRequest>(100);
@@ -86,18 +142,44 @@ fn build_ui(app: &Application) {
     let (avatar_done_tx, mut avatar_done_rx) = mpsc::channel::<(String, PathBuf)>(100);
 
     // Create channel for control commands
-    let (control_tx, mut control_rx) = mpsc::channel::<ControlCommand>(100);
+    let (control_tx, mut control_rx) = mpsc::channel::<ControlRequest>(100);
+
+    // Create channel for commands sent back to the plugin
+    let (plugin_tx, _) = broadcast::channel::<PluginCommand>(16);
 
     // Set avatar sender in renderer
     renderer.borrow_mut().set_avatar_sender(avatar_tx);
-
-    // Spawn WebSocket server in tokio runtime
-    std::thread::spawn(move || {
-        let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
-        rt.block_on(async {
-            let server = WebSocketServer::new(WEBSOCKET_PORT);
-            server.run(event_tx).await;
-        });
+    renderer.borrow_mut().set_role_colors(config.role_colors);
+    renderer.borrow_mut().set_avatar_size(config.avatar_size);
+
+    // Start the event sources enabled in config, each on its own thread
+    let protocol_stats = Arc::new(ProtocolStats::default());
+    let recorder = cli.record.as_deref().and_then(|path| {
+        FrameRecorder::create(path)
+            .map_err(|e| tracing::error!("Cannot record to {:?}: {}", path, e))
+            .ok()
+    });
+    let context = SourceContext {
+        listen_addr: cli.listen_addr(&config),
+        plugin_commands: plugin_tx.clone(),
+        protocol_stats: protocol_stats.clone(),
+        recorder,
+        replay: cli.replay(),
+    };
+    let sources = Rc::new(RefCell::new(event_source::from_config(&config, &context)));
+    for source in sources.borrow_mut().iter_mut() {
+        info!("Starting event source {}", source.name());
+        source.start(event_tx.clone());
+    }
+    let source_status: Vec<(&'static str, StatusHandle)> =
+        sources.borrow().iter().map(|s| (s.name(), s.status())).collect();
+
+    // Stop the sources when GTK quits, so their socket and port files go away
+    let sources_clone = sources.clone();
+    app.connect_shutdown(move |_| {
+        for source in sources_clone.borrow_mut().iter_mut() {
+            source.stop();
+        }
     });
 
     // Spawn avatar download handler
@@ -108,7 +190,7 @@ fn build_ui(app: &Application) {
             let cache = AvatarCache::new();
 
             while let Some(request) = avatar_rx.recv().await {
-                if let Some(path) = cache.get_avatar(&request.user_id, &request.avatar_hash).await {
+                if let Some(path) = cache.get_avatar(&request.user_id, request.guild_id.as_deref(), &request.avatar_hash).await {
                     let _ = avatar_done_tx_clone.send((request.user_id, path)).await;
                 }
             }
@@ -116,85 +198,139 @@ fn build_ui(app: &Application) {
     });
 
     // Spawn IPC control server
-    let control_tx_clone = control_tx.clone();
+    let control_server = Arc::new(ControlIpcServer::new(protocol_stats.clone(), source_status));
     std::thread::spawn(move || {
         let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
         rt.block_on(async {
-            use tokio::io::AsyncReadExt;
-            use tokio::net::UnixListener;
-
-            let socket_path = ControlIpcServer::get_socket_path();
-
-            // Remove old socket if exists
-            if socket_path.exists() {
-                let _ = std::fs::remove_file(&socket_path);
-            }
-
-            match UnixListener::bind(&socket_path) {
-                Ok(listener) => {
-                    info!("IPC control server listening on {:?}", socket_path);
-
-                    loop {
-                        match listener.accept().await {
-                            Ok((mut stream, _)) => {
-                                let mut buffer = vec![0u8; 4096];
-
-                                match stream.read(&mut buffer).await {
-                                    Ok(n) if n > 0 => {
-                                        match serde_json::from_slice::<ControlCommand>(&buffer[..n]) {
-                                            Ok(command) => {
-                                                info!("Received IPC command: {:?}", command);
-                                                let _ = control_tx_clone.send(command).await;
-                                            }
-                                            Err(e) => {
-                                                tracing::error!("Failed to deserialize command: {}", e);
-                                            }
-                                        }
-                                    }
-                                    Err(e) => {
-                                        tracing::error!("Failed to read from socket: {}", e);
-                                    }
-                                    _ => {}
-                                }
-                            }
-                            Err(e) => {
-                                tracing::error!("Failed to accept connection: {}", e);
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    tracing::error!("Failed to bind IPC socket: {}", e);
-                }
+            if let Err(e) = control_server.run(control_tx).await {
+                tracing::error!("Failed to bind IPC socket: {}", e);
             }
         });
     });
 
+    // Voice overlay redraws, coalesced per frame
+    let refresh = OverlayRefresh::new(&window, renderer.clone(), sessions.clone());
+
+    // Config the daemon runs with, replaced by UpdateConfig
+    let current_config = Rc::new(RefCell::new(config));
+
+    // Layout last sent by a plugin, applied again when `settings_source` switches to Plugin
+    let plugin_settings = Rc::new(RefCell::new(None::<ConfigMessage>));
+
     // Setup GTK main context to receive overlay events
     let renderer_clone = renderer.clone();
+    let sessions_clone = sessions.clone();
+    let refresh_clone = refresh.clone();
     let notification_window_clone = notification_window.clone();
+    let chat_window_clone = chat_window.clone();
+    let window_clone = window.clone();
+    let config_clone = current_config.clone();
+    let plugin_settings_clone = plugin_settings.clone();
     glib::spawn_future_local(async move {
-        while let Some(event) = event_rx.recv().await {
-            match event {
-                OverlayEvent::ChannelJoined(users, channel_name) => {
-                    info!("Channel joined: {} with {} users", channel_name, users.len());
-                    renderer_clone.borrow_mut().on_channel_joined(users, channel_name);
-                }
-                OverlayEvent::ChannelLeft => {
-                    info!("Channel left");
-                    renderer_clone.borrow_mut().on_channel_left();
-                }
-                OverlayEvent::VoiceStateUpdate(update) => {
-                    renderer_clone.borrow_mut().on_voice_state_update(update);
-                }
-                OverlayEvent::ConfigReceived(config) => {
-                    info!("Config received from user: {:?}", config.user_id);
+        while let Some(first) = event_rx.recv().await {
+            // Apply everything already queued, then let GTK draw the result once
+            let mut batch = vec![first];
+            while batch.len() < MAX_EVENTS_PER_BATCH {
+                match event_rx.try_recv() {
+                    Ok(event) => batch.push(event),
+                    Err(_) => break,
                 }
-                OverlayEvent::MessageNotification(notif) => {
-                    info!("Message notification: {}", notif.title);
-                    notification_window_clone.borrow_mut().show_notification(notif);
+            }
+
+            let mut roster_changed = false;
+            for SessionEvent { session_id, event } in batch {
+                match event {
+                    OverlayEvent::ChannelJoined(users, channel_name, channel_type) => {
+                        info!("[{}] Channel joined: {} ({:?}) with {} users",
+                            session_id, channel_name, channel_type, users.len());
+                        sessions_clone.borrow_mut().on_channel_joined(&session_id, users, channel_name, channel_type);
+                    }
+                    OverlayEvent::ChannelLeft => {
+                        info!("[{}] Channel left", session_id);
+                        sessions_clone.borrow_mut().on_channel_left(&session_id);
+                    }
+                    OverlayEvent::VoiceStateUpdate(update) => {
+                        sessions_clone.borrow_mut().on_voice_state_update(&session_id, update);
+                    }
+                    OverlayEvent::SnapshotRequested => {
+                        sessions_clone.borrow_mut().on_snapshot_requested(&session_id);
+                        continue;
+                    }
+                    OverlayEvent::VoiceSnapshot(snapshot) => {
+                        info!("[{}] Voice snapshot: {:?} with {} users",
+                            session_id, snapshot.channel_name, snapshot.states.len());
+                        sessions_clone.borrow_mut().on_snapshot(&session_id, snapshot);
+                    }
+                    OverlayEvent::ConfigReceived(config) => {
+                        info!("Config received from user: {:?}", config.user_id);
+                        sessions_clone.borrow_mut().on_activity(&session_id);
+                        let local = config_clone.borrow();
+                        if local.settings_source == SettingsSource::Plugin {
+                            apply_plugin_settings(
+                                &config,
+                                &window_clone,
+                                local.margin,
+                                &renderer_clone,
+                                &notification_window_clone,
+                            );
+                        }
+                        *plugin_settings_clone.borrow_mut() = Some(config);
+                    }
+                    OverlayEvent::MessageNotification(notif) => {
+                        info!("Message notification: {}", notif.title);
+                        notification_window_clone.borrow_mut().show_notification(notif);
+                        continue;
+                    }
+                    OverlayEvent::ChannelMessage(message) => {
+                        // Only the chat of a voice channel the overlay shows
+                        if sessions_clone.borrow().shows_channel(&message.channel_id) {
+                            chat_window_clone.borrow_mut().show_message(message);
+                        }
+                        continue;
+                    }
+                    OverlayEvent::SessionRenamed(previous) => {
+                        sessions_clone.borrow_mut().on_session_renamed(&previous, &session_id);
+                    }
+                    OverlayEvent::SessionClosed => {
+                        info!("[{}] Plugin closed its connection, clearing its roster", session_id);
+                        sessions_clone.borrow_mut().on_session_closed(&session_id);
+                    }
+                    OverlayEvent::SourceLost => {
+                        info!("[{}] Plugin lost, dimming its roster", session_id);
+                        let token = sessions_clone.borrow_mut().on_source_lost(&session_id);
+
+                        // Clear the stale roster unless the plugin comes back in time
+                        if let Some(token) = token {
+                            let sessions = sessions_clone.clone();
+                            let refresh = refresh_clone.clone();
+                            let source_lost_clear =
+                                std::time::Duration::from_secs(config_clone.borrow().source_lost_clear_secs);
+                            glib::timeout_add_local_once(source_lost_clear, move || {
+                                if sessions.borrow_mut().clear_lost(&session_id, token) {
+                                    info!("[{}] Plugin did not come back, clearing its roster", session_id);
+                                    refresh.request();
+                                }
+                            });
+                        }
+                    }
                 }
+                roster_changed = true;
+            }
+
+            if !roster_changed {
+                continue;
+            }
+
+            // Drop the chat of a channel the overlay no longer shows
+            let stale = chat_window_clone
+                .borrow()
+                .channel_id()
+                .is_some_and(|id| !sessions_clone.borrow().shows_channel(id));
+            if stale {
+                chat_window_clone.borrow_mut().clear();
             }
+
+            refresh_clone.request();
         }
     });
 
@@ -208,30 +344,131 @@ fn build_ui(app: &Application) {
 
     // Setup GTK main context to receive control commands
     let renderer_clone3 = renderer.clone();
+    let sessions_clone2 = sessions.clone();
+    let chat_window_clone2 = chat_window.clone();
+    let notification_window_clone2 = notification_window.clone();
+    let overlay_window = window.clone();
+    let cli = cli.clone();
     glib::spawn_future_local(async move {
-        while let Some(command) = control_rx.recv().await {
-            match command {
+        while let Some(request) = control_rx.recv().await {
+            let response = match &request.command {
                 ControlCommand::EnableTestMode => {
                     info!("Enabling test mode");
                     renderer_clone3.borrow_mut().enable_test_mode();
+                    ControlResponse::ok()
                 }
                 ControlCommand::DisableTestMode => {
                     info!("Disabling test mode");
-                    renderer_clone3.borrow_mut().disable_test_mode();
+                    refresh.request();
+                    ControlResponse::ok()
+                }
+                ControlCommand::UpdateConfig(new_config) => {
+                    // Zero limits would close every plugin connection, a bad address could not be bound
+                    let mut checked = new_config.as_ref().clone();
+                    let mut problems: Vec<String> = checked
+                        .clamp_limits()
+                        .into_iter()
+                        .map(|key| format!("{} must be greater than 0", key))
+                        .collect();
+                    if checked.clamp_bind_address() {
+                        problems.push(format!("bind_address {:?} is not an IP address", new_config.bind_address));
+                    }
+                    if !problems.is_empty() {
+                        let message = problems.join(", ");
+                        warn!("Rejected config update: {}", message);
+                        ControlResponse::error(message)
+                    } else {
+                        info!("Applying config update");
+                        let old_config = current_config.replace((**new_config).clone());
+
+                        reload_css(&css_provider, new_config);
+                        // With plugin settings the corners and transparency are the plugin's choice
+                        match (new_config.settings_source, &*plugin_settings.borrow()) {
+                            (SettingsSource::Local, _) => {
+                                anchor_window(&overlay_window, new_config.position, new_config.margin);
+                                renderer_clone3.borrow().set_semitransparent(false);
+                                notification_window_clone2.borrow().reset_layout();
+                            }
+                            (SettingsSource::Plugin, Some(settings)) => apply_plugin_settings(
+                                settings,
+                                &overlay_window,
+                                new_config.margin,
+                                &renderer_clone3,
+                                &notification_window_clone2,
+                            ),
+                            (SettingsSource::Plugin, None) => {}
+                        }
+                        set_click_through(&overlay_window, new_config.click_through);
+                        {
+                            let mut renderer = renderer_clone3.borrow_mut();
+                            renderer.set_avatar_size(new_config.avatar_size);
+                            renderer.set_role_colors(new_config.role_colors);
+                        }
+                        chat_window_clone2.borrow_mut().apply_config(new_config);
+                        sessions_clone2
+                            .borrow_mut()
+                            .set_mode(new_config.session_mode, new_config.pinned_session.clone());
+                        refresh.request();
+
+                        // Sources keep their connections unless a changed setting needs a restart
+                        let listen_addr = cli.listen_addr(new_config);
+                        for source in sources.borrow_mut().iter_mut() {
+                            if source.reconfigure(new_config, listen_addr) {
+                                info!("Restarting event source {} for the new config", source.name());
+                                source.stop();
+                                source.start(event_tx.clone());
+                            }
+                        }
+                        if new_config.event_sources != old_config.event_sources {
+                            warn!("Changes to event_sources apply after a daemon restart");
+                        }
+
+                        ControlResponse::ok()
+                    }
+                }
+                ControlCommand::ToggleMute => {
+                    send_to_plugin(&plugin_tx, &sessions_clone2.borrow(), OutgoingMessage::ToggleMute)
+                }
+                ControlCommand::ToggleDeafen => {
+                    send_to_plugin(&plugin_tx, &sessions_clone2.borrow(), OutgoingMessage::ToggleDeaf)
+                }
+                ControlCommand::DisconnectVoice => {
+                    send_to_plugin(&plugin_tx, &sessions_clone2.borrow(), OutgoingMessage::Disconnect)
                 }
-                ControlCommand::UpdateConfig(_config) => {
-                    info!("Config update received (not yet implemented)");
-                    // TODO: Reload CSS and reposition windows
+                ControlCommand::MoveToChannel(channel_id) => send_to_plugin(
+                    &plugin_tx,
+                    &sessions_clone2.borrow(),
+                    OutgoingMessage::MoveToChannel { channel_id: channel_id.clone() },
+                ),
+                ControlCommand::GetConfig => ControlResponse::with_data(&*current_config.borrow()),
+                ControlCommand::GetRoster => ControlResponse::with_data(&sessions_clone2.borrow().roster_report()),
+                ControlCommand::GetSessions => ControlResponse::with_data(&sessions_clone2.borrow().session_reports()),
+                ControlCommand::ShowOverlay => {
+                    overlay_window.set_visible(true);
+                    ControlResponse::ok()
                 }
-                ControlCommand::Restart => {
-                    info!("Restart requested (not yet implemented)");
-                    // TODO: Implement daemon restart
+                ControlCommand::HideOverlay => {
+                    overlay_window.set_visible(false);
+                    ControlResponse::ok()
+                }
+                ControlCommand::ToggleOverlay => {
+                    let visible = !overlay_window.is_visible();
+                    overlay_window.set_visible(visible);
+                    ControlResponse::with_data(&serde_json::json!({ "visible": visible }))
+                }
+                ControlCommand::GetStatus | ControlCommand::GetProtocolStats | ControlCommand::GetSourceStatus => {
+                    // Answered directly by the control socket server
+                    ControlResponse::ok()
                 }
                 ControlCommand::Quit => {
                     info!("Quit requested");
+                    for source in sources.borrow_mut().iter_mut() {
+                        source.stop();
+                    }
                     std::process::exit(0);
                 }
-            }
+            };
+            request.respond(response);
         }
     });
 
@@ -239,3 +476,54 @@ fn build_ui(app: &Application) {
     notification_window.borrow().present();
     info!("Overlay windows created and displayed");
 }
+
+/// Apply the layout picked in the plugin's settings (`settings_source = "Plugin"`)
+fn apply_plugin_settings(
+    settings: &ConfigMessage,
+    window: &ApplicationWindow,
+    margin: i32,
+    renderer: &Rc<RefCell<OverlayRenderer>>,
+    notification_window: &Rc<RefCell<NotificationWindow>>,
+) {
+    if let Some(alignment) = &settings.user_alignment {
+        match settings.user_position() {
+            Some(position) => anchor_window(window, position, margin),
+            None => warn!("Ignoring unknown userAlignment {:?}", alignment),
+        }
+    }
+    if let Some(alignment) = &settings.message_alignment {
+        match settings.message_position() {
+            Some(position) => notification_window.borrow().set_position(position),
+            None => warn!("Ignoring unknown messageAlignment {:?}", alignment),
+        }
+    }
+    if let Some(semitransparent) = settings.voice_semitransparent {
+        renderer.borrow().set_semitransparent(semitransparent);
+    }
+    if let Some(semitransparent) = settings.messages_semitransparent {
+        notification_window.borrow().set_semitransparent(semitransparent);
+    }
+}
+
+/// Forward a voice control command to the plugin of the session the overlay is showing
+fn send_to_plugin(
+    plugin_tx: &broadcast::Sender<PluginCommand>,
+    sessions: &SessionManager,
+    message: OutgoingMessage,
+) -> ControlResponse {
+    // Toggling mute on every connected Discord client at once is never what was asked for
+    let Some(session_id) = sessions.control_target() else {
+        warn!("No plugin session to control, command dropped");
+        return ControlResponse::error("No plugin session to send the command to");
+    };
+    info!("Forwarding to plugin session {}: {:?}", session_id, message);
+    let command = PluginCommand {
+        session_id: session_id.to_string(),
+        message,
+    };
+    if plugin_tx.send(command).is_err() {
+        tracing::warn!("No plugin connected, command dropped");
+        return ControlResponse::error("No plugin connected");
+    }
+    ControlResponse::ok()
+}
@@ -5,9 +5,14 @@ use std::cell::RefCell;
 use std::io::Read;
 use std::rc::Rc;
 use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::discord_data::NotificationContent;
-use crate::config::Config;
+use crate::config::{Config, Position};
+use crate::overlay_window::anchor_window;
+
+/// Distance between the notifications and the screen edge
+const NOTIFICATION_MARGIN: i32 = 20;
 
 /// Notification window positioned at bottom right
 pub struct NotificationWindow {
@@ -46,21 +51,14 @@ impl NotificationWindow {
     }
 
     fn setup_notification_layer_shell(window: &ApplicationWindow, _config: &Config) {
-        use gtk4_layer_shell::{Edge, Layer, LayerShell};
+        use gtk4_layer_shell::{Layer, LayerShell};
 
         window.init_layer_shell();
         window.set_layer(Layer::Overlay);
         window.set_keyboard_mode(gtk4_layer_shell::KeyboardMode::None);
 
-        // Anchor to bottom right
-        window.set_anchor(Edge::Bottom, true);
-        window.set_anchor(Edge::Right, true);
-        window.set_anchor(Edge::Top, false);
-        window.set_anchor(Edge::Left, false);
-
-        // Margins
-        window.set_margin(Edge::Bottom, 20);
-        window.set_margin(Edge::Right, 20);
+        // Anchor to bottom right until the plugin asks otherwise
+        anchor_window(window, Position::BottomRight, NOTIFICATION_MARGIN);
 
         // DON'T apply click-through on notifications - we want to be able to click them!
 
@@ -68,13 +66,46 @@ impl NotificationWindow {
         window.set_namespace("discord-overlay-notifications");
     }
 
+    /// Move the notifications to another screen corner (plugin `messageAlignment`)
+    pub fn set_position(&self, position: Position) {
+        anchor_window(&self.window, position, NOTIFICATION_MARGIN);
+
+        // Stack new notifications towards the anchored corner
+        let (halign, valign) = match position {
+            Position::TopLeft => (Align::Start, Align::Start),
+            Position::TopRight => (Align::End, Align::Start),
+            Position::BottomLeft => (Align::Start, Align::End),
+            Position::BottomRight => (Align::End, Align::End),
+        };
+        self.container.set_halign(halign);
+        self.container.set_valign(valign);
+    }
+
+    /// Back to the layout used without plugin settings: bottom right, opaque
+    pub fn reset_layout(&self) {
+        self.set_position(Position::BottomRight);
+        self.set_semitransparent(false);
+    }
+
+    /// Lighter notification background (plugin `messagesSemitransparent`)
+    pub fn set_semitransparent(&self, enabled: bool) {
+        if enabled {
+            self.container.add_css_class("semitransparent");
+        } else {
+            self.container.remove_css_class("semitransparent");
+        }
+    }
+
     pub fn show_notification(&mut self, notif: NotificationContent) {
-        tracing::info!("Showing notification: title='{}', body='{}', icon={:?}",
-            notif.title, notif.body, notif.icon);
+        tracing::info!("Showing notification: title='{}', body='{}', icon={:?}, author={:?}",
+            notif.title, notif.body, notif.icon, notif.author_id);
 
         // Create notification container
         let notif_box = GtkBox::new(Orientation::Horizontal, 12);
         notif_box.add_css_class("notification");
+        if notif.mentioned {
+            notif_box.add_css_class("mention");
+        }
 
         // Avatar container
         let avatar_box = GtkBox::new(Orientation::Vertical, 0);
@@ -95,29 +126,59 @@ impl NotificationWindow {
                 Err(e) => {
                     tracing::error!("Failed to download avatar: {}", e);
                     // Fallback to placeholder
-                    let placeholder = Self::create_avatar_placeholder(&notif.title);
+                    let placeholder = Self::create_avatar_placeholder(Self::author(&notif));
                     avatar_box.append(&placeholder);
                 }
             }
         } else {
             tracing::info!("No icon URL provided, using placeholder");
             // No icon provided, use placeholder
-            let placeholder = Self::create_avatar_placeholder(&notif.title);
+            let placeholder = Self::create_avatar_placeholder(Self::author(&notif));
             avatar_box.append(&placeholder);
         }
 
         notif_box.append(&avatar_box);
 
-        // Content container (title + body)
+        // Content container (title + subtitle + body)
         let content_box = GtkBox::new(Orientation::Vertical, 6);
         content_box.set_hexpand(true);
 
+        // Title row: title, mention badge, time
+        let title_row = GtkBox::new(Orientation::Horizontal, 6);
+
         let title = Label::new(Some(&notif.title));
         title.add_css_class("notification-title");
         title.set_halign(Align::Start);
+        title.set_hexpand(true);
+        title.set_xalign(0.0);
         title.set_ellipsize(gtk4::pango::EllipsizeMode::End);
         title.set_max_width_chars(30);
-        content_box.append(&title);
+        title_row.append(&title);
+
+        if notif.mentioned {
+            let badge = Label::new(Some("@"));
+            badge.add_css_class("notification-mention-badge");
+            badge.set_valign(Align::Center);
+            title_row.append(&badge);
+        }
+
+        if let Some(timestamp) = notif.timestamp {
+            let time = Label::new(Some(&Self::relative_time(timestamp)));
+            time.add_css_class("notification-time");
+            time.set_valign(Align::Center);
+            title_row.append(&time);
+        }
+
+        content_box.append(&title_row);
+
+        if let Some(subtitle_text) = notif.subtitle() {
+            let subtitle = Label::new(Some(&subtitle_text));
+            subtitle.add_css_class("notification-subtitle");
+            subtitle.set_halign(Align::Start);
+            subtitle.set_ellipsize(gtk4::pango::EllipsizeMode::End);
+            subtitle.set_max_width_chars(36);
+            content_box.append(&subtitle);
+        }
 
         let body = Label::new(Some(&notif.body));
         body.add_css_class("notification-body");
@@ -130,11 +191,21 @@ impl NotificationWindow {
         body.set_valign(Align::Start);
         content_box.append(&body);
 
+        if let Some(summary) = notif.attachments_summary() {
+            let attachments = Label::new(Some(&summary));
+            attachments.add_css_class("notification-attachments");
+            attachments.set_halign(Align::Start);
+            attachments.set_ellipsize(gtk4::pango::EllipsizeMode::Middle);
+            attachments.set_max_width_chars(36);
+            content_box.append(&attachments);
+        }
+
         notif_box.append(&content_box);
 
         // Add click handler to open Equibop
         let gesture = GestureClick::new();
         let channel_id = notif.channel_id.clone();
+        let message_id = notif.message_id.clone();
         gesture.connect_released(move |_gesture, _n_press, _x, _y| {
             tracing::info!("Notification clicked! Opening Equibop...");
 
@@ -145,7 +216,7 @@ impl NotificationWindow {
             // Note: Discord/Equibop doesn't support direct channel opening via CLI
             // but we log it for future use or custom protocol handlers
             if let Some(ref channel) = channel_id {
-                tracing::info!("Attempting to open channel: {}", channel);
+                tracing::info!("Attempting to open channel: {} (message {:?})", channel, message_id);
                 // Could implement a custom protocol handler here in the future
                 // For now, just open Equibop normally
             }
@@ -188,6 +259,27 @@ impl NotificationWindow {
         });
     }
 
+    /// Name used for the avatar placeholder: the author when known, the title otherwise
+    fn author(notif: &NotificationContent) -> &str {
+        notif.author_name.as_deref().unwrap_or(&notif.title)
+    }
+
+    /// "now", "5m ago", "3h ago" or "2d ago" for a millisecond timestamp
+    fn relative_time(timestamp_ms: u64) -> String {
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let secs = now_ms.saturating_sub(timestamp_ms) / 1000;
+
+        match secs {
+            0..=59 => "now".to_string(),
+            60..=3599 => format!("{}m ago", secs / 60),
+            3600..=86399 => format!("{}h ago", secs / 3600),
+            _ => format!("{}d ago", secs / 86400),
+        }
+    }
+
     fn create_avatar_placeholder(username: &str) -> GtkBox {
         let placeholder_box = GtkBox::new(Orientation::Vertical, 0);
         placeholder_box.add_css_class("notification-avatar-placeholder");
@@ -13,68 +13,97 @@ pub fn setup_layer_shell(window: &ApplicationWindow, config: &Config) {
     // Set layer to Overlay (above everything else)
     window.set_layer(Layer::Overlay);
 
-    // Anchor based on position
-    match config.position {
+    anchor_window(window, config.position, config.margin);
+
+    // Keyboard passthrough - let keys go to underlying windows
+    window.set_keyboard_mode(KeyboardMode::None);
+
+    // Click-through - let mouse events pass through overlay
+    if config.click_through {
+        // Must be done after window is realized (surface exists)
+        window.connect_realize(|window| set_click_through(window, true));
+    }
+
+    // Set namespace for compositor identification
+    window.set_namespace("discord-overlay");
+}
+
+/// Anchor a layer-shell window to a screen corner
+pub fn anchor_window(window: &ApplicationWindow, position: Position, margin: i32) {
+    match position {
         Position::TopRight => {
             window.set_anchor(Edge::Top, true);
             window.set_anchor(Edge::Right, true);
             window.set_anchor(Edge::Bottom, false);
             window.set_anchor(Edge::Left, false);
-            window.set_margin(Edge::Top, config.margin);
-            window.set_margin(Edge::Right, config.margin);
+            window.set_margin(Edge::Top, margin);
+            window.set_margin(Edge::Right, margin);
         }
         Position::TopLeft => {
             window.set_anchor(Edge::Top, true);
             window.set_anchor(Edge::Left, true);
             window.set_anchor(Edge::Bottom, false);
             window.set_anchor(Edge::Right, false);
-            window.set_margin(Edge::Top, config.margin);
-            window.set_margin(Edge::Left, config.margin);
+            window.set_margin(Edge::Top, margin);
+            window.set_margin(Edge::Left, margin);
         }
         Position::BottomRight => {
             window.set_anchor(Edge::Bottom, true);
             window.set_anchor(Edge::Right, true);
             window.set_anchor(Edge::Top, false);
             window.set_anchor(Edge::Left, false);
-            window.set_margin(Edge::Bottom, config.margin);
-            window.set_margin(Edge::Right, config.margin);
+            window.set_margin(Edge::Bottom, margin);
+            window.set_margin(Edge::Right, margin);
         }
         Position::BottomLeft => {
             window.set_anchor(Edge::Bottom, true);
             window.set_anchor(Edge::Left, true);
             window.set_anchor(Edge::Top, false);
             window.set_anchor(Edge::Right, false);
-            window.set_margin(Edge::Bottom, config.margin);
-            window.set_margin(Edge::Left, config.margin);
+            window.set_margin(Edge::Bottom, margin);
+            window.set_margin(Edge::Left, margin);
         }
     }
+}
 
-    // Keyboard passthrough - let keys go to underlying windows
-    window.set_keyboard_mode(KeyboardMode::None);
+/// Set an empty input region so mouse events go to the windows below, or restore a full one
+pub fn set_click_through(window: &ApplicationWindow, enabled: bool) {
+    // Not realized yet: `setup_layer_shell` takes care of it
+    let Some(surface) = window.surface() else {
+        return;
+    };
+
+    let region = if enabled {
+        gdk::cairo::Region::create()
+    } else {
+        // Clipped to the surface by the compositor
+        gdk::cairo::Region::create_rectangle(&gdk::cairo::RectangleInt::new(0, 0, i32::MAX, i32::MAX))
+    };
+    surface.set_input_region(&region);
+}
 
-    // Click-through - let mouse events pass through overlay
-    if config.click_through {
-        // Set empty input region to make window click-through
-        // Must be done after window is realized (surface exists)
-        let window_clone = window.clone();
-        window.connect_realize(move |_| {
-            if let Some(surface) = window_clone.surface() {
-                let empty_region = gdk::cairo::Region::create();
-                surface.set_input_region(&empty_region);
-            }
-        });
-    }
+/// Load and apply CSS styles for the overlay; keep the provider for `reload_css`
+pub fn load_css(config: &Config) -> CssProvider {
+    let provider = CssProvider::new();
+    provider.load_from_data(&generate_css(config));
 
-    // Set namespace for compositor identification
-    window.set_namespace("discord-overlay");
+    gtk4::style_context_add_provider_for_display(
+        &gtk4::gdk::Display::default().expect("Could not get default display"),
+        &provider,
+        gtk4::STYLE_PROVIDER_PRIORITY_APPLICATION,
+    );
+
+    provider
 }
 
-/// Load and apply CSS styles for the overlay
-pub fn load_css(config: &Config) {
-    let provider = CssProvider::new();
+/// Replace the styles of a provider from `load_css` with ones for `config`
+pub fn reload_css(provider: &CssProvider, config: &Config) {
+    provider.load_from_data(&generate_css(config));
+}
 
-    // Generate CSS with config values
-    let css = format!(
+/// Generate CSS with config values
+fn generate_css(config: &Config) -> String {
+    format!(
         r#"
 /* Discord Overlay - Dark Theme */
 
@@ -89,6 +118,35 @@ window {{
     min-width: 180px;
 }}
 
+.overlay-container.semitransparent {{
+    background-color: rgba(30, 31, 34, 0.45);
+}}
+
+.overlay-container.source-lost .users-box {{
+    opacity: 0.4;
+}}
+
+.source-lost-badge {{
+    color: #f0b232;
+    font-size: 11px;
+    font-weight: 600;
+    padding: 2px 8px 6px 8px;
+}}
+
+/* Stage channels */
+.stage-header {{
+    color: #949ba4;
+    font-size: 11px;
+    font-weight: 600;
+    padding: 2px 8px 4px 8px;
+}}
+
+.stage-audience {{
+    color: #949ba4;
+    font-size: 12px;
+    padding: 4px 8px 2px 8px;
+}}
+
 .user-row {{
     padding: 6px 8px;
     border-radius: 4px;
@@ -166,6 +224,29 @@ window {{
     color: #ed4245;
 }}
 
+.status-icon.self-muted,
+.status-icon.self-deafened {{
+    color: #949ba4;
+}}
+
+.status-icon.server-muted,
+.status-icon.server-deafened {{
+    color: #ed4245;
+}}
+
+.status-icon.suppressed {{
+    color: #949ba4;
+}}
+
+.status-icon.priority-speaker {{
+    color: #f0b232;
+}}
+
+.status-icon.hand-raised {{
+    color: #f0b232;
+}}
+
+.status-icon.video,
 .status-icon.streaming {{
     color: #23a55a;
 }}
@@ -190,6 +271,29 @@ window {{
     margin-bottom: 4px;
 }}
 
+/* Channel chat window */
+.chat-window {{
+    background-color: rgba(30, 31, 34, {chat_opacity});
+    border-radius: 8px;
+    padding: 6px 10px;
+    transition: opacity 600ms ease-out;
+}}
+
+.chat-window.faded {{
+    opacity: 0;
+}}
+
+.chat-author {{
+    color: #ffffff;
+    font-size: 13px;
+    font-weight: 700;
+}}
+
+.chat-content {{
+    color: #dbdee1;
+    font-size: 13px;
+}}
+
 /* Notification Window */
 .notification-window {{
     background-color: transparent;
@@ -210,6 +314,10 @@ window {{
     transition: all 0.2s ease;
 }}
 
+.notification-window.semitransparent .notification {{
+    background-color: rgba(30, 31, 34, 0.6);
+}}
+
 .notification.clickable:hover {{
     background-color: rgba(40, 41, 44, 0.98);
     border-left-color: #7289da;
@@ -269,16 +377,40 @@ window {{
     line-height: 1.5;
     opacity: 0.95;
 }}
+
+.notification-subtitle {{
+    color: #949ba4;
+    font-size: 12px;
+    font-weight: 600;
+}}
+
+.notification-time {{
+    color: #949ba4;
+    font-size: 11px;
+}}
+
+.notification-attachments {{
+    color: #00a8fc;
+    font-size: 12px;
+}}
+
+/* Mentions */
+.notification.mention {{
+    border-left-color: #f0b232;
+    background-color: rgba(240, 178, 50, 0.12);
+}}
+
+.notification-mention-badge {{
+    background-color: #f23f43;
+    color: white;
+    border-radius: 8px;
+    padding: 0 6px;
+    font-size: 11px;
+    font-weight: 700;
+}}
 "#,
         opacity = config.opacity,
         avatar_size = config.avatar_size,
-    );
-
-    provider.load_from_data(&css);
-
-    gtk4::style_context_add_provider_for_display(
-        &gtk4::gdk::Display::default().expect("Could not get default display"),
-        &provider,
-        gtk4::STYLE_PROVIDER_PRIORITY_APPLICATION,
-    );
+        chat_opacity = config.chat_opacity,
+    )
 }
@@ -0,0 +1,59 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+use crate::config::Config;
+
+/// Shared secret the plugin must present in its first frame
+pub struct PairingToken;
+
+impl PairingToken {
+    /// Token file, next to config.toml so it survives reboots
+    pub fn path() -> PathBuf {
+        Config::config_path().with_file_name("pairing-token")
+    }
+
+    /// Read the current token, generating one on first use
+    pub fn load_or_create() -> io::Result<String> {
+        match fs::read_to_string(Self::path()) {
+            Ok(token) if !token.trim().is_empty() => Ok(token.trim().to_string()),
+            _ => Self::regenerate(),
+        }
+    }
+
+    /// Replace the token; plugins paired with the old one must be paired again.
+    /// A token that could not be saved is never returned, the daemon would not know it.
+    pub fn regenerate() -> io::Result<String> {
+        let token = Self::generate()?;
+        let path = Self::path();
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Only the owner may read the token
+        let written = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)
+            .and_then(|mut file| file.write_all(token.as_bytes()));
+
+        if let Err(e) = written {
+            warn!("Failed to save pairing token: {}", e);
+            return Err(e);
+        }
+
+        info!("Pairing token written to {:?}", path);
+        Ok(token)
+    }
+
+    fn generate() -> io::Result<String> {
+        let mut bytes = [0u8; 16];
+        fs::File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut bytes))?;
+        Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+}
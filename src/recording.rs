@@ -0,0 +1,220 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::discord_data::SessionEvent;
+use crate::event_source::{EventSource, SourceStatus, SourceThread, StatusHandle};
+use crate::ipc::{Connection, ProtocolStats};
+
+/// One line of a recording
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    /// Milliseconds since the Unix epoch
+    pub ts: u64,
+    /// Per-daemon connection number, frames of one plugin connection share it
+    pub connection: u64,
+    /// Raw text frame as received
+    pub frame: String,
+}
+
+/// Appends every incoming plugin frame to a JSONL file (`--record`)
+#[derive(Clone)]
+pub struct FrameRecorder {
+    writer: Arc<Mutex<BufWriter<File>>>,
+}
+
+impl FrameRecorder {
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        // Recordings hold voice rosters and messages, only the owner may read them
+        let file = File::options().create(true).append(true).mode(0o600).open(path)?;
+        info!("Recording plugin frames to {:?}", path);
+        Ok(Self {
+            writer: Arc::new(Mutex::new(BufWriter::new(file))),
+        })
+    }
+
+    pub fn record(&self, connection: u64, frame: &str) {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let line = RecordedFrame {
+            ts,
+            connection,
+            frame: redact_token(frame),
+        };
+
+        let Ok(mut writer) = self.writer.lock() else {
+            return;
+        };
+        // Flush per frame so a crash still leaves the frames that led to it
+        let written = serde_json::to_writer(&mut *writer, &line)
+            .map_err(std::io::Error::from)
+            .and_then(|()| writer.write_all(b"\n"))
+            .and_then(|()| writer.flush());
+        if let Err(e) = written {
+            warn!("Failed to record frame: {}", e);
+        }
+    }
+}
+
+/// The frame with its pairing token (HELLO, REGISTER_CONFIG) blanked out.
+/// Anything else, malformed frames included, is kept byte for byte.
+fn redact_token(frame: &str) -> String {
+    if !frame.contains("\"token\"") {
+        return frame.to_string();
+    }
+    match serde_json::from_str::<serde_json::Value>(frame) {
+        Ok(mut value) if value.get("token").is_some_and(|token| !token.is_null()) => {
+            value["token"] = serde_json::Value::from("<redacted>");
+            value.to_string()
+        }
+        _ => frame.to_string(),
+    }
+}
+
+/// Event source feeding a recording through the plugin parsing path (`--replay`)
+pub struct ReplaySource {
+    path: PathBuf,
+    /// 1.0 replays in real time, 10.0 ten times faster, 0 without any delay
+    speed: f64,
+    stats: Arc<ProtocolStats>,
+    status: StatusHandle,
+    thread: Option<SourceThread>,
+}
+
+impl ReplaySource {
+    pub fn new(path: PathBuf, speed: f64, stats: Arc<ProtocolStats>) -> Self {
+        Self {
+            path,
+            speed,
+            stats,
+            status: StatusHandle::default(),
+            thread: None,
+        }
+    }
+}
+
+impl EventSource for ReplaySource {
+    fn name(&self) -> &'static str {
+        "replay"
+    }
+
+    fn start(&mut self, tx: mpsc::Sender<SessionEvent>) {
+        if self.thread.is_some() {
+            return;
+        }
+
+        self.status.set(SourceStatus::Running(format!("replaying {}", self.path.display())));
+        let task = replay(self.path.clone(), self.speed, self.stats.clone(), tx, self.status.clone());
+        self.thread = Some(SourceThread::spawn(self.name(), task));
+    }
+
+    fn stop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            thread.stop();
+        }
+        self.status.set(SourceStatus::Stopped);
+    }
+
+    fn status(&self) -> StatusHandle {
+        self.status.clone()
+    }
+}
+
+async fn replay(
+    path: PathBuf,
+    speed: f64,
+    stats: Arc<ProtocolStats>,
+    tx: mpsc::Sender<SessionEvent>,
+    status: StatusHandle,
+) {
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            status.set(SourceStatus::Failed(format!("cannot open {}: {}", path.display(), e)));
+            return;
+        }
+    };
+
+    let mut connections: HashMap<u64, Connection> = HashMap::new();
+    let mut previous_ts: Option<u64> = None;
+    let mut replayed = 0;
+
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let Ok(line) = line else { break };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let recorded: RecordedFrame = match serde_json::from_str(&line) {
+            Ok(recorded) => recorded,
+            Err(e) => {
+                warn!("Skipping line {} of {:?}: {}", number + 1, path, e);
+                continue;
+            }
+        };
+
+        // Keep the recorded pacing, scaled by the replay speed
+        if let Some(previous) = previous_ts {
+            let gap = recorded.ts.saturating_sub(previous);
+            if speed > 0.0 && gap > 0 {
+                tokio::time::sleep(Duration::from_millis(gap).div_f64(speed)).await;
+            }
+        }
+        previous_ts = Some(recorded.ts);
+
+        let connection = connections
+            .entry(recorded.connection)
+            .or_insert_with(|| Connection::replay(recorded.connection, stats.clone()));
+
+        let events = match connection.handle_frame(&recorded.frame) {
+            Ok(events) => events,
+            Err(reason) => {
+                warn!("Recorded connection {} would be closed: {}", recorded.connection, reason);
+                connections.remove(&recorded.connection);
+                continue;
+            }
+        };
+        // Nobody is listening for replies
+        connection.take_replies();
+
+        for event in events {
+            let event = SessionEvent {
+                session_id: connection.session_id().to_string(),
+                event,
+            };
+            if tx.send(event).await.is_err() {
+                return;
+            }
+        }
+        replayed += 1;
+    }
+
+    info!("Replay of {:?} finished after {} frames", path, replayed);
+    status.set(SourceStatus::Stopped);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairing_token_is_not_recorded() {
+        let hello = r#"{"cmd":"HELLO","protocolVersion":1,"token":"secret"}"#;
+        let recorded = redact_token(hello);
+        assert!(!recorded.contains("secret"));
+        assert!(recorded.contains(r#""cmd":"HELLO""#));
+
+        for frame in [r#"{"cmd":"CHANNEL_LEFT"}"#, r#"{"cmd":"HELLO","token":null}"#, "{\"token\": broken"] {
+            assert_eq!(redact_token(frame), frame);
+        }
+    }
+}
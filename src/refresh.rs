@@ -0,0 +1,80 @@
+use gtk4::prelude::*;
+use gtk4::{glib, ApplicationWindow};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use tracing::debug;
+
+use crate::renderer::OverlayRenderer;
+use crate::session::SessionManager;
+
+/// Redraws the voice overlay at most once per GDK frame.
+/// Events only update `SessionManager`; whatever state they leave behind is drawn on the
+/// next frame, so a burst of speaking flips for one user costs a single row update.
+pub struct OverlayRefresh {
+    window: ApplicationWindow,
+    renderer: Rc<RefCell<OverlayRenderer>>,
+    sessions: Rc<RefCell<SessionManager>>,
+    /// A frame callback is already installed
+    pending: Cell<bool>,
+    /// Requests since the last redraw, for the debug log
+    requests: Cell<u64>,
+}
+
+impl OverlayRefresh {
+    pub fn new(
+        window: &ApplicationWindow,
+        renderer: Rc<RefCell<OverlayRenderer>>,
+        sessions: Rc<RefCell<SessionManager>>,
+    ) -> Rc<Self> {
+        Rc::new(Self {
+            window: window.clone(),
+            renderer,
+            sessions,
+            pending: Cell::new(false),
+            requests: Cell::new(0),
+        })
+    }
+
+    /// Redraw on the next frame; further requests before it are free
+    pub fn request(self: &Rc<Self>) {
+        self.requests.set(self.requests.get() + 1);
+        self.schedule();
+    }
+
+    fn schedule(self: &Rc<Self>) {
+        if self.pending.replace(true) {
+            return;
+        }
+
+        let this = self.clone();
+        if self.window.is_mapped() {
+            self.window.add_tick_callback(move |_, _| {
+                this.redraw();
+                glib::ControlFlow::Break
+            });
+        } else {
+            // No frame clock ticks for an unmapped window
+            glib::idle_add_local_once(move || this.redraw());
+        }
+    }
+
+    /// Show the sessions picked by config, dimmed if their plugin is gone
+    fn redraw(self: &Rc<Self>) {
+        self.pending.set(false);
+        let requests = self.requests.replace(0);
+
+        let deferred = {
+            let sessions = self.sessions.borrow();
+            let mut renderer = self.renderer.borrow_mut();
+            let deferred = renderer.set_roster(&sessions.visible_users(), sessions.visible_channel_type());
+            renderer.set_source_lost(sessions.visible_source_lost());
+            deferred
+        };
+
+        debug!("Overlay redraw for {} requests, {} rows deferred", requests, deferred);
+        // Rows over the per-frame budget are picked up by the next frame
+        if deferred > 0 {
+            self.schedule();
+        }
+    }
+}
@@ -1,26 +1,41 @@
 use gtk4::prelude::*;
 use gtk4::{Align, Box as GtkBox, Label, Orientation, Picture};
 use gtk4::gdk_pixbuf::Pixbuf;
-use std::collections::HashMap;
+use gtk4::pango::{self, AttrColor, AttrList};
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use tokio::sync::mpsc;
 
-use crate::discord_data::{VoiceUser, VoiceUserPartial};
+use crate::discord_data::{ChannelType, VoiceUser};
 
 /// Message to request avatar loading
 #[derive(Debug, Clone)]
 pub struct AvatarRequest {
     pub user_id: String,
     pub avatar_hash: String,
+    /// Set for per-server avatars
+    pub guild_id: Option<String>,
 }
 
+/// Rows added or updated per `set_roster` call at most; the rest wait for the next frame
+pub const MAX_ROW_UPDATES_PER_FRAME: usize = 32;
+
 /// Renders the voice overlay UI
 pub struct OverlayRenderer {
     container: GtkBox,
+    lost_badge: Label,
+    /// "Speakers" title, only shown in stage channels
+    speakers_header: Label,
     users_box: GtkBox,
+    /// Collapsed stage audience ("12 listening")
+    audience_label: Label,
     user_widgets: HashMap<String, UserWidget>,
     users: HashMap<String, VoiceUser>,
     avatar_tx: Option<mpsc::Sender<AvatarRequest>>,
+    /// Color usernames with the user's `roleColor`
+    role_colors: bool,
+    /// Avatar edge in pixels
+    avatar_size: i32,
 }
 
 struct UserWidget {
@@ -34,20 +49,44 @@ impl OverlayRenderer {
         let container = GtkBox::new(Orientation::Vertical, 0);
         container.add_css_class("overlay-container");
 
+        // Shown while the roster is stale because the plugin went away
+        let lost_badge = Label::new(Some("Discord disconnected"));
+        lost_badge.add_css_class("source-lost-badge");
+        lost_badge.set_halign(Align::Start);
+        lost_badge.set_visible(false);
+        container.append(&lost_badge);
+
+        let speakers_header = Label::new(Some("Speakers"));
+        speakers_header.add_css_class("stage-header");
+        speakers_header.set_halign(Align::Start);
+        speakers_header.set_visible(false);
+        container.append(&speakers_header);
+
         // Users container
         let users_box = GtkBox::new(Orientation::Vertical, 0);
         users_box.add_css_class("users-box");
         container.append(&users_box);
 
+        let audience_label = Label::new(None);
+        audience_label.add_css_class("stage-audience");
+        audience_label.set_halign(Align::Start);
+        audience_label.set_visible(false);
+        container.append(&audience_label);
+
         // Initially hidden
         container.set_visible(false);
 
         Self {
             container,
+            lost_badge,
+            speakers_header,
             users_box,
+            audience_label,
             user_widgets: HashMap::new(),
             users: HashMap::new(),
             avatar_tx: None,
+            role_colors: false,
+            avatar_size: 32,
         }
     }
 
@@ -55,6 +94,34 @@ impl OverlayRenderer {
         self.avatar_tx = Some(tx);
     }
 
+    pub fn set_role_colors(&mut self, enabled: bool) {
+        if self.role_colors == enabled {
+            return;
+        }
+        self.role_colors = enabled;
+
+        // Recolor the rows already on screen
+        for (user_id, user_widget) in &self.user_widgets {
+            if let Some(user) = self.users.get(user_id) {
+                Self::update_user_widget(&user_widget.row, user, Some(user), enabled);
+            }
+        }
+    }
+
+    /// Change the avatar size, reloading the avatars already on screen at the new size
+    pub fn set_avatar_size(&mut self, size: i32) {
+        if self.avatar_size == size {
+            return;
+        }
+        self.avatar_size = size;
+
+        let users: Vec<VoiceUser> = self.users.values().cloned().collect();
+        for user in &users {
+            self.reset_avatar(user);
+            self.request_avatar(user);
+        }
+    }
+
     pub fn widget(&self) -> &GtkBox {
         &self.container
     }
@@ -72,6 +139,8 @@ impl OverlayRenderer {
                 mute: false,
                 streaming: false,
                 speaking: true,
+                priority_speaker: true,
+                ..Default::default()
             },
             VoiceUser {
                 user_id: "test2".to_string(),
@@ -82,6 +151,8 @@ impl OverlayRenderer {
                 mute: true,
                 streaming: false,
                 speaking: false,
+                self_mute: true,
+                ..Default::default()
             },
             VoiceUser {
                 user_id: "test3".to_string(),
@@ -92,22 +163,21 @@ impl OverlayRenderer {
                 mute: false,
                 streaming: true,
                 speaking: false,
+                self_deaf: true,
+                video: true,
+                ..Default::default()
             },
         ];
 
-        self.on_channel_joined(test_users, "Test Channel".to_string());
-    }
-
-    /// Disable test mode
-    pub fn disable_test_mode(&mut self) {
-        self.on_channel_left();
+        self.set_roster(&test_users, ChannelType::Voice);
+        self.set_source_lost(false);
     }
 
     /// Update avatar for a user
     pub fn set_avatar(&mut self, user_id: &str, path: &PathBuf) {
         if let Some(user_widget) = self.user_widgets.get_mut(user_id) {
             // Load pixbuf and create picture
-            if let Ok(pixbuf) = Pixbuf::from_file_at_scale(path, 32, 32, true) {
+            if let Ok(pixbuf) = Pixbuf::from_file_at_scale(path, self.avatar_size, self.avatar_size, true) {
                 // Remove old content from avatar frame
                 while let Some(child) = user_widget.avatar_frame.first_child() {
                     user_widget.avatar_frame.remove(&child);
@@ -115,172 +185,123 @@ impl OverlayRenderer {
 
                 let picture = Picture::for_pixbuf(&pixbuf);
                 picture.add_css_class("avatar");
-                picture.set_size_request(32, 32);
+                picture.set_size_request(self.avatar_size, self.avatar_size);
                 user_widget.avatar_frame.append(&picture);
                 user_widget.avatar_picture = Some(picture);
             }
         }
     }
 
-    /// Handle channel joined - set all users
-    pub fn on_channel_joined(&mut self, users: Vec<VoiceUser>, _channel_name: String) {
-        // Clear existing
-        self.clear();
-
-        // Add all users
-        for user in users {
-            self.add_user(user);
-        }
-
-        self.container.set_visible(!self.users.is_empty());
-    }
-
-    /// Handle channel left - clear all users
-    pub fn on_channel_left(&mut self) {
-        self.clear();
-        self.container.set_visible(false);
-    }
-
-    /// Handle voice state update
-    pub fn on_voice_state_update(&mut self, update: VoiceUserPartial) {
-        tracing::info!("Voice state update: user_id={}, channel_id={:?}, username={:?}, speaking={:?}, mute={:?}, deaf={:?}, streaming={:?}",
-            update.user_id, update.channel_id, update.username, update.speaking, update.mute, update.deaf, update.streaming);
-
-        // Check if user left or changed channel
-        // CRITICAL: The OrbolayBridge plugin has TWO different handlers that send VOICE_STATE_UPDATE:
-        // 1. handleSpeaking: sends only { userId, speaking } - channelId is OMITTED (undefined in JS)
-        // 2. handleVoiceStateUpdates: sends full state including channelId (can be null if disconnected)
-        //
-        // Problem: Both "omitted field" and "null value" deserialize as None in Rust!
-        // Solution: Use heuristic - if ONLY speaking is provided (all other fields are None),
-        // it's a SPEAKING update and we ignore channelId. Otherwise, it's a full update.
-
-        let is_speaking_only_update = update.username.is_none()
-            && update.avatar_url.is_none()
-            && update.deaf.is_none()
-            && update.mute.is_none()
-            && update.streaming.is_none()
-            && update.speaking.is_some();
-
-        let user_left = if is_speaking_only_update {
-            // This is a SPEAKING update - ignore channelId (it's omitted, not null)
-            false
-        } else {
-            match &update.channel_id {
-                None => {
-                    // In a full VOICE_STATE_UPDATE, channelId: None means null (disconnected)
-                    tracing::info!("User {} disconnected from voice (channelId is null in full update)", update.user_id);
-                    true
-                },
-                Some(ch) if ch.is_empty() => {
-                    // Empty string also means disconnection
-                    tracing::info!("User {} disconnected from voice (empty channelId)", update.user_id);
-                    true
-                },
-                Some(ch) => {
-                    // User is in a channel - check if it's different from their current one
-                    if let Some(user) = self.users.get(&update.user_id) {
-                        if let Some(our_channel) = &user.channel_id {
-                            if ch != our_channel {
-                                tracing::info!("User {} moved from channel {} to {}", update.user_id, our_channel, ch);
-                                true
-                            } else {
-                                false
-                            }
-                        } else {
-                            false
-                        }
-                    } else {
-                        // User not in our list yet - don't remove them
-                        false
-                    }
-                },
-            }
-        };
-
-        if user_left {
-            tracing::info!("User {} left the voice channel", update.user_id);
-            self.remove_user(&update.user_id);
-            self.container.set_visible(!self.users.is_empty());
-            return;
+    /// Show exactly these users, updating only the rows that changed.
+    /// Stage channels list speakers and raised hands, and only count the rest of the audience.
+    /// Returns how many changed rows were left for the next call by `MAX_ROW_UPDATES_PER_FRAME`
+    pub fn set_roster(&mut self, users: &[VoiceUser], channel_type: ChannelType) -> usize {
+        let stage = channel_type == ChannelType::Stage;
+        let (users, audience): (Vec<&VoiceUser>, Vec<&VoiceUser>) = users
+            .iter()
+            .partition(|u| !stage || !u.suppress || u.hand_raised);
+
+        let visible: HashSet<&str> = users.iter().map(|u| u.user_id.as_str()).collect();
+        let gone: Vec<String> = self
+            .users
+            .keys()
+            .filter(|id| !visible.contains(id.as_str()))
+            .cloned()
+            .collect();
+        for user_id in gone {
+            self.remove_user(&user_id);
         }
 
-        if let Some(user) = self.users.get_mut(&update.user_id) {
-            // Update existing user
-            if let Some(speaking) = update.speaking {
-                user.speaking = speaking;
-            }
-            if let Some(mute) = update.mute {
-                user.mute = mute;
-            }
-            if let Some(deaf) = update.deaf {
-                user.deaf = deaf;
+        // Once the budget is spent every later change waits, so rows keep their order
+        let mut budget = MAX_ROW_UPDATES_PER_FRAME;
+        let mut deferred = 0;
+        for user in users {
+            if self.users.get(&user.user_id) == Some(user) {
+                continue;
             }
-            if let Some(streaming) = update.streaming {
-                user.streaming = streaming;
+            if budget == 0 {
+                deferred += 1;
+                continue;
             }
-
-            // Update username if provided (might be missing initially after daemon restart)
-            if let Some(username) = update.username {
-                if !username.is_empty() && user.username.is_empty() {
-                    user.username = username;
-                    // Re-render widget with new username
-                    if let Some(user_widget) = self.user_widgets.get(&update.user_id) {
-                        Self::update_user_widget(&user_widget.row, user);
+            budget -= 1;
+
+            match self.users.get(&user.user_id) {
+                None => self.add_user(user.clone()),
+                Some(current) => {
+                    let avatar_changed = current.avatar() != user.avatar();
+                    if let Some(user_widget) = self.user_widgets.get(&user.user_id) {
+                        Self::update_user_widget(&user_widget.row, user, Some(current), self.role_colors);
+                    }
+                    self.users.insert(user.user_id.clone(), user.clone());
+                    if avatar_changed {
+                        self.reset_avatar(user);
+                        self.request_avatar(user);
                     }
                 }
             }
+        }
 
-            // Update widget
-            if let Some(user_widget) = self.user_widgets.get(&update.user_id) {
-                Self::update_user_widget(&user_widget.row, user);
-            }
+        self.speakers_header.set_visible(stage);
+        self.audience_label.set_text(&format!("👥 {} listening", audience.len()));
+        self.audience_label.set_visible(stage && !audience.is_empty());
+
+        self.container.set_visible(!self.users.is_empty() || !audience.is_empty());
+        deferred
+    }
+
+    /// Lighter background, set from the plugin's `voiceSemitransparent`
+    pub fn set_semitransparent(&self, enabled: bool) {
+        if enabled {
+            self.container.add_css_class("semitransparent");
         } else {
-            // User not in our list - add them!
-            // This handles daemon restarts where we receive VOICE_STATE_UPDATE before CHANNEL_JOINED
-            // If we're receiving updates about this user, they must be in a voice channel
-            tracing::info!("Adding unknown user {} (daemon likely restarted)", update.user_id);
-            let user = VoiceUser {
-                user_id: update.user_id.clone(),
-                username: update.username.unwrap_or_else(|| format!("User {}", &update.user_id[..8])),
-                avatar_url: update.avatar_url,
-                channel_id: update.channel_id,
-                deaf: update.deaf.unwrap_or(false),
-                mute: update.mute.unwrap_or(false),
-                streaming: update.streaming.unwrap_or(false),
-                speaking: update.speaking.unwrap_or(false),
-            };
-            self.add_user(user);
+            self.container.remove_css_class("semitransparent");
         }
-
-        self.container.set_visible(!self.users.is_empty());
     }
 
-    fn clear(&mut self) {
-        for (_, user_widget) in self.user_widgets.drain() {
-            self.users_box.remove(&user_widget.row);
+    /// Dim the overlay and show a badge while the roster comes from a lost plugin
+    pub fn set_source_lost(&self, lost: bool) {
+        if lost {
+            self.container.add_css_class("source-lost");
+        } else {
+            self.container.remove_css_class("source-lost");
         }
-        self.users.clear();
+        self.lost_badge.set_visible(lost);
     }
 
     fn add_user(&mut self, user: VoiceUser) {
         let user_widget = self.create_user_widget(&user);
         self.users_box.append(&user_widget.row);
 
-        // Request avatar download if available
-        if let (Some(tx), Some(avatar_hash)) = (&self.avatar_tx, &user.avatar_url) {
+        self.request_avatar(&user);
+
+        self.user_widgets.insert(user.user_id.clone(), user_widget);
+        self.users.insert(user.user_id.clone(), user);
+    }
+
+    /// Request avatar download if available
+    fn request_avatar(&self, user: &VoiceUser) {
+        if let (Some(tx), Some((avatar_hash, guild_id))) = (&self.avatar_tx, user.avatar()) {
             let request = AvatarRequest {
                 user_id: user.user_id.clone(),
-                avatar_hash: avatar_hash.clone(),
+                avatar_hash: avatar_hash.to_string(),
+                guild_id: guild_id.map(str::to_string),
             };
             let tx = tx.clone();
             gtk4::glib::spawn_future_local(async move {
                 let _ = tx.send(request).await;
             });
         }
+    }
 
-        self.user_widgets.insert(user.user_id.clone(), user_widget);
-        self.users.insert(user.user_id.clone(), user);
+    /// Put the initials placeholder back in a user's avatar frame
+    fn reset_avatar(&mut self, user: &VoiceUser) {
+        if let Some(user_widget) = self.user_widgets.get_mut(&user.user_id) {
+            while let Some(child) = user_widget.avatar_frame.first_child() {
+                user_widget.avatar_frame.remove(&child);
+            }
+            user_widget.avatar_frame.append(&Self::create_initials(user));
+            user_widget.avatar_picture = None;
+        }
     }
 
     fn remove_user(&mut self, user_id: &str) {
@@ -302,22 +323,11 @@ impl OverlayRenderer {
         avatar_frame.set_valign(Align::Center);
 
         // Avatar placeholder with initials (will be replaced by actual avatar)
-        let initials_box = GtkBox::new(Orientation::Vertical, 0);
-        initials_box.add_css_class("avatar-placeholder");
-        initials_box.set_valign(Align::Center);
-        initials_box.set_halign(Align::Center);
-
-        let initials = Label::new(Some(&user.initials()));
-        initials.add_css_class("avatar-initials");
-        initials.set_valign(Align::Center);
-        initials.set_halign(Align::Center);
-        initials_box.append(&initials);
-
-        avatar_frame.append(&initials_box);
+        avatar_frame.append(&Self::create_initials(user));
         row.append(&avatar_frame);
 
         // Username
-        let username = Label::new(Some(&user.username));
+        let username = Label::new(Some(user.display_name()));
         username.add_css_class("username");
         username.set_widget_name("username");
         username.set_halign(Align::Start);
@@ -333,7 +343,7 @@ impl OverlayRenderer {
         row.append(&status_box);
 
         // Apply initial state
-        Self::update_user_widget(&row, user);
+        Self::update_user_widget(&row, user, None, self.role_colors);
 
         UserWidget {
             row,
@@ -342,7 +352,57 @@ impl OverlayRenderer {
         }
     }
 
-    fn update_user_widget(widget: &GtkBox, user: &VoiceUser) {
+    fn create_initials(user: &VoiceUser) -> GtkBox {
+        let initials_box = GtkBox::new(Orientation::Vertical, 0);
+        initials_box.add_css_class("avatar-placeholder");
+        initials_box.set_valign(Align::Center);
+        initials_box.set_halign(Align::Center);
+
+        let initials = Label::new(Some(&user.initials()));
+        initials.add_css_class("avatar-initials");
+        initials.set_valign(Align::Center);
+        initials.set_halign(Align::Center);
+        initials_box.append(&initials);
+
+        initials_box
+    }
+
+    /// Icon and CSS class for every state flag set on a user.
+    /// Plain `mute`/`deaf` only get an icon when the plugin didn't say who muted them
+    fn status_icons(user: &VoiceUser) -> Vec<(&'static str, &'static str)> {
+        let deaf = user.deaf || user.self_deaf || user.server_deaf;
+        let unknown_mute = user.mute && !user.self_mute && !user.server_mute;
+        let unknown_deaf = user.deaf && !user.self_deaf && !user.server_deaf;
+
+        [
+            (unknown_mute && !deaf, "🔇", "muted"),
+            (user.self_mute && !deaf, "🔇", "self-muted"),
+            (user.server_mute && !user.server_deaf, "🚫", "server-muted"),
+            (unknown_deaf, "🔕", "deafened"),
+            (user.self_deaf, "🔕", "self-deafened"),
+            (user.server_deaf, "⛔", "server-deafened"),
+            (user.suppress, "💤", "suppressed"),
+            (user.priority_speaker, "📢", "priority-speaker"),
+            (user.hand_raised, "✋", "hand-raised"),
+            (user.video, "📷", "video"),
+            (user.streaming, "📺", "streaming"),
+        ]
+        .into_iter()
+        .filter(|(set, _, _)| *set)
+        .map(|(_, icon, class)| (icon, class))
+        .collect()
+    }
+
+    /// Foreground color attribute for the user's role, if it parses
+    fn role_color_attrs(user: &VoiceUser) -> Option<AttrList> {
+        let color = pango::Color::parse(user.role_color.as_deref()?).ok()?;
+        let attrs = AttrList::new();
+        attrs.insert(AttrColor::new_foreground(color.red(), color.green(), color.blue()));
+        Some(attrs)
+    }
+
+    /// Bring a row in line with `user`; `previous` is what the row currently shows
+    fn update_user_widget(widget: &GtkBox, user: &VoiceUser, previous: Option<&VoiceUser>, role_colors: bool) {
         // Update speaking state on avatar frame
         let mut child = widget.first_child();
         while let Some(c) = child {
@@ -358,45 +418,41 @@ impl OverlayRenderer {
                 }
             } else if name == "username" {
                 if let Some(label) = c.downcast_ref::<Label>() {
+                    if label.text() != user.display_name() {
+                        label.set_text(user.display_name());
+                    }
+                    // Speaking keeps its green highlight over the role color
+                    let attrs = if role_colors && !user.speaking {
+                        Self::role_color_attrs(user)
+                    } else {
+                        None
+                    };
+                    label.set_attributes(attrs.as_ref());
                     if user.speaking {
                         label.add_css_class("speaking");
                     } else {
                         label.remove_css_class("speaking");
                     }
-                    if user.mute || user.deaf {
+                    if user.mute || user.deaf || user.suppress {
                         label.add_css_class("muted");
                     } else {
                         label.remove_css_class("muted");
                     }
                 }
             } else if name == "status-icons" {
-                if let Some(status_box) = c.downcast_ref::<GtkBox>() {
+                let icons = Self::status_icons(user);
+                // Speaking flips leave the icons alone, don't rebuild them
+                let unchanged = previous.is_some_and(|p| Self::status_icons(p) == icons);
+                if let Some(status_box) = c.downcast_ref::<GtkBox>().filter(|_| !unchanged) {
                     // Clear existing icons
                     while let Some(icon) = status_box.first_child() {
                         status_box.remove(&icon);
                     }
 
-                    // Add muted icon
-                    if user.mute && !user.deaf {
-                        let icon = Label::new(Some("🔇"));
-                        icon.add_css_class("status-icon");
-                        icon.add_css_class("muted");
-                        status_box.append(&icon);
-                    }
-
-                    // Add deafened icon
-                    if user.deaf {
-                        let icon = Label::new(Some("🔕"));
-                        icon.add_css_class("status-icon");
-                        icon.add_css_class("deafened");
-                        status_box.append(&icon);
-                    }
-
-                    // Add streaming icon
-                    if user.streaming {
-                        let icon = Label::new(Some("📺"));
+                    for (icon_text, class) in icons {
+                        let icon = Label::new(Some(icon_text));
                         icon.add_css_class("status-icon");
-                        icon.add_css_class("streaming");
+                        icon.add_css_class(class);
                         status_box.append(&icon);
                     }
                 }
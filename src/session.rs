@@ -0,0 +1,580 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use crate::config::SessionMode;
+use crate::discord_data::{ChannelType, Field, VoiceSnapshotMessage, VoiceUser, VoiceUserPartial};
+
+/// How long partial updates wait for a requested VOICE_SNAPSHOT before being applied again
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Voice state reported by one plugin connection (one Discord client)
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    /// Voice channel the client is in (learned from CHANNEL_JOINED or the first full update)
+    pub channel_id: Option<String>,
+    /// Not known when the channel was learned from a voice state update
+    pub channel_name: Option<String>,
+    pub channel_type: ChannelType,
+    /// Users in the channel, in join order
+    pub users: Vec<VoiceUser>,
+    /// Logical time of the last channel join, used by `SessionMode::LastJoined`
+    joined_at: u64,
+    /// When a VOICE_SNAPSHOT was requested; unknown users wait for it instead of being added
+    snapshot_requested_at: Option<Instant>,
+    /// Logical time the plugin was declared lost, cleared by any traffic from it
+    lost_at: Option<u64>,
+}
+
+impl Session {
+    fn user_mut(&mut self, user_id: &str) -> Option<&mut VoiceUser> {
+        self.users.iter_mut().find(|u| u.user_id == user_id)
+    }
+
+    fn remove_user(&mut self, user_id: &str) {
+        self.users.retain(|u| u.user_id != user_id);
+    }
+
+    /// A requested snapshot may still arrive (plugins without support never answer)
+    fn awaiting_snapshot(&self) -> bool {
+        self.snapshot_requested_at
+            .is_some_and(|requested| requested.elapsed() < SNAPSHOT_TIMEOUT)
+    }
+
+    fn on_channel_joined(&mut self, users: Vec<VoiceUser>, channel_name: String, channel_type: ChannelType) {
+        // A full roster, as good as the snapshot
+        self.snapshot_requested_at = None;
+        self.channel_id = users.iter().find_map(|u| u.channel_id.clone());
+        self.channel_name = Some(channel_name).filter(|name| !name.is_empty());
+        self.channel_type = channel_type;
+        self.users = users;
+    }
+
+    fn on_channel_left(&mut self) {
+        self.channel_id = None;
+        self.channel_name = None;
+        self.channel_type = ChannelType::Voice;
+        self.users.clear();
+    }
+
+    /// Replace the whole roster with the plugin's snapshot. Returns true if the client is in voice.
+    fn on_snapshot(&mut self, snapshot: VoiceSnapshotMessage) -> bool {
+        self.snapshot_requested_at = None;
+
+        let Some(channel_id) = snapshot.channel_id.filter(|id| !id.is_empty()) else {
+            self.on_channel_left();
+            return false;
+        };
+
+        // Drop anyone the plugin reports in another channel
+        self.users = snapshot
+            .states
+            .into_iter()
+            .filter(|u| u.channel_id.as_ref().is_none_or(|ch| *ch == channel_id))
+            .collect();
+        self.channel_id = Some(channel_id);
+        self.channel_name = snapshot.channel_name.filter(|name| !name.is_empty());
+        self.channel_type = snapshot.channel_type;
+        true
+    }
+
+    /// Apply a VOICE_STATE_UPDATE. Returns true if this session just learned its channel.
+    fn on_voice_state_update(&mut self, update: VoiceUserPartial) -> bool {
+        let mut learned_channel = false;
+
+        // The plugin sends two shapes of VOICE_STATE_UPDATE:
+        // - speaking updates, which omit channelId entirely (membership unchanged)
+        // - full state updates, which carry channelId (null once the user disconnects)
+        match &update.channel_id {
+            Field::Absent => {}
+            Field::Null => {
+                info!("User {} disconnected from voice", update.user_id);
+                self.remove_user(&update.user_id);
+                return false;
+            }
+            Field::Value(ch) if ch.is_empty() => {
+                info!("User {} disconnected from voice (empty channelId)", update.user_id);
+                self.remove_user(&update.user_id);
+                return false;
+            }
+            Field::Value(ch) => match &self.channel_id {
+                Some(our_channel) if ch != our_channel => {
+                    if self.users.iter().any(|u| u.user_id == update.user_id) {
+                        info!("User {} moved from channel {} to {}", update.user_id, our_channel, ch);
+                        self.remove_user(&update.user_id);
+                    }
+                    return false;
+                }
+                Some(_) => {}
+                None => {
+                    // No CHANNEL_JOINED seen yet (daemon restarted mid-call)
+                    info!("Following channel {} from voice state update", ch);
+                    self.channel_id = Some(ch.clone());
+                    learned_channel = true;
+                }
+            },
+        }
+
+        if let Some(user) = self.user_mut(&update.user_id) {
+            let flags = [
+                (&mut user.speaking, update.speaking),
+                (&mut user.mute, update.mute),
+                (&mut user.deaf, update.deaf),
+                (&mut user.streaming, update.streaming),
+                (&mut user.self_mute, update.self_mute),
+                (&mut user.self_deaf, update.self_deaf),
+                (&mut user.server_mute, update.server_mute),
+                (&mut user.server_deaf, update.server_deaf),
+                (&mut user.suppress, update.suppress),
+                (&mut user.video, update.video),
+                (&mut user.priority_speaker, update.priority_speaker),
+                (&mut user.hand_raised, update.hand_raised),
+            ];
+            for (flag, value) in flags {
+                if let Some(value) = value {
+                    *flag = value;
+                }
+            }
+            if let Field::Value(channel_id) = update.channel_id {
+                user.channel_id = Some(channel_id);
+            }
+
+            // Update username if provided (might be missing initially after daemon restart)
+            if let Some(username) = update.username {
+                if !username.is_empty() && user.username.is_empty() {
+                    user.username = username;
+                }
+            }
+
+            update.avatar_url.apply_to(&mut user.avatar_url);
+            update.nick.apply_to(&mut user.nick);
+            update.guild_avatar.apply_to(&mut user.guild_avatar);
+            update.guild_id.apply_to(&mut user.guild_id);
+            update.role_color.apply_to(&mut user.role_color);
+        } else if self.awaiting_snapshot() {
+            // The snapshot will bring this user with their real name and avatar
+            debug!("Deferring unknown user {} until the snapshot arrives", update.user_id);
+        } else if let (Field::Value(channel_id), Some(username)) =
+            (update.channel_id, update.username.filter(|name| !name.is_empty()))
+        {
+            // Full update for a user we don't know yet, in our channel
+            info!("Adding unknown user {}", update.user_id);
+            self.users.push(VoiceUser {
+                user_id: update.user_id.clone(),
+                username,
+                avatar_url: update.avatar_url.into_option(),
+                channel_id: Some(channel_id),
+                deaf: update.deaf.unwrap_or(false),
+                mute: update.mute.unwrap_or(false),
+                streaming: update.streaming.unwrap_or(false),
+                speaking: update.speaking.unwrap_or(false),
+                self_mute: update.self_mute.unwrap_or(false),
+                self_deaf: update.self_deaf.unwrap_or(false),
+                server_mute: update.server_mute.unwrap_or(false),
+                server_deaf: update.server_deaf.unwrap_or(false),
+                suppress: update.suppress.unwrap_or(false),
+                video: update.video.unwrap_or(false),
+                priority_speaker: update.priority_speaker.unwrap_or(false),
+                hand_raised: update.hand_raised.unwrap_or(false),
+                nick: update.nick.into_option(),
+                guild_avatar: update.guild_avatar.into_option(),
+                guild_id: update.guild_id.into_option(),
+                role_color: update.role_color.into_option(),
+            });
+        } else {
+            // Speaking update, or a nameless one, for someone we have no roster entry for
+            debug!("Ignoring update for unknown user {} without channelId or username", update.user_id);
+        }
+
+        learned_channel
+    }
+}
+
+/// One plugin session, as sent to control clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionReport {
+    pub session_id: String,
+    pub channel_id: Option<String>,
+    pub channel_name: Option<String>,
+    pub channel_type: ChannelType,
+    pub user_count: usize,
+    /// Shown by the overlay under the current session mode
+    pub visible: bool,
+    /// The plugin is gone and its roster is kept around dimmed
+    pub source_lost: bool,
+}
+
+/// What the overlay shows, as sent to control clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RosterReport {
+    /// Channels of the shown sessions (several in `Merged` mode)
+    pub channels: Vec<ChannelReport>,
+    pub channel_type: ChannelType,
+    pub source_lost: bool,
+    pub users: Vec<VoiceUser>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelReport {
+    pub channel_id: String,
+    pub channel_name: Option<String>,
+}
+
+/// Per-session voice state for every connected Discord client
+pub struct SessionManager {
+    sessions: HashMap<String, Session>,
+    mode: SessionMode,
+    pinned: Option<String>,
+    clock: u64,
+}
+
+impl SessionManager {
+    pub fn new(mode: SessionMode, pinned: Option<String>) -> Self {
+        let mut manager = Self {
+            sessions: HashMap::new(),
+            mode: SessionMode::LastJoined,
+            pinned: None,
+            clock: 0,
+        };
+        manager.set_mode(mode, pinned);
+        manager
+    }
+
+    /// Change which sessions are shown; the sessions themselves are kept
+    pub fn set_mode(&mut self, mode: SessionMode, pinned: Option<String>) {
+        if mode == SessionMode::Pinned && pinned.is_none() {
+            warn!("session_mode = \"Pinned\" without pinned_session, showing the last joined session");
+        }
+        self.mode = mode;
+        self.pinned = pinned;
+    }
+
+    /// Session receiving an event; any event means its plugin is (back) online
+    fn session_mut(&mut self, session_id: &str) -> &mut Session {
+        let session = self.sessions.entry(session_id.to_string()).or_default();
+        session.lost_at = None;
+        session
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    pub fn on_channel_joined(
+        &mut self,
+        session_id: &str,
+        users: Vec<VoiceUser>,
+        channel_name: String,
+        channel_type: ChannelType,
+    ) {
+        let now = self.tick();
+        let session = self.session_mut(session_id);
+        session.on_channel_joined(users, channel_name, channel_type);
+        session.joined_at = now;
+    }
+
+    pub fn on_channel_left(&mut self, session_id: &str) {
+        self.session_mut(session_id).on_channel_left();
+    }
+
+    pub fn on_snapshot_requested(&mut self, session_id: &str) {
+        self.session_mut(session_id).snapshot_requested_at = Some(Instant::now());
+    }
+
+    pub fn on_snapshot(&mut self, session_id: &str, snapshot: VoiceSnapshotMessage) {
+        let now = self.tick();
+        let session = self.session_mut(session_id);
+        if session.on_snapshot(snapshot) {
+            session.joined_at = now;
+        }
+    }
+
+    pub fn on_voice_state_update(&mut self, session_id: &str, update: VoiceUserPartial) {
+        let now = self.tick();
+        let session = self.session_mut(session_id);
+        if session.on_voice_state_update(update) {
+            session.joined_at = now;
+        }
+    }
+
+    /// The connection behind `previous` registered as `session_id`.
+    /// Whatever it reported before that moves over, unless it had nothing to show.
+    pub fn on_session_renamed(&mut self, previous: &str, session_id: &str) {
+        let Some(session) = self.sessions.remove(previous) else {
+            return;
+        };
+        if session.channel_id.is_some() || !self.sessions.contains_key(session_id) {
+            self.sessions.insert(session_id.to_string(), session);
+        }
+    }
+
+    /// The plugin closed its connection on purpose (quit, reload), so nothing is kept around
+    pub fn on_session_closed(&mut self, session_id: &str) {
+        self.sessions.remove(session_id);
+    }
+
+    /// Traffic that carries no voice state (e.g. REGISTER_CONFIG after a reconnect)
+    pub fn on_activity(&mut self, session_id: &str) {
+        if let Some(session) = self.sessions.get_mut(session_id) {
+            session.lost_at = None;
+        }
+    }
+
+    /// Mark a session's plugin as gone. Returns a token for `clear_lost`,
+    /// or `None` if there is nothing on screen to keep around.
+    pub fn on_source_lost(&mut self, session_id: &str) -> Option<u64> {
+        let now = self.tick();
+        let session = self.sessions.get_mut(session_id)?;
+        if session.users.is_empty() {
+            self.sessions.remove(session_id);
+            return None;
+        }
+        session.lost_at = Some(now);
+        Some(now)
+    }
+
+    /// Drop a lost session if its plugin hasn't come back since `on_source_lost` returned `token`
+    pub fn clear_lost(&mut self, session_id: &str, token: u64) -> bool {
+        let still_lost = self
+            .sessions
+            .get(session_id)
+            .is_some_and(|s| s.lost_at == Some(token));
+        if still_lost {
+            self.sessions.remove(session_id);
+        }
+        still_lost
+    }
+
+    /// Sessions shown by the overlay according to the session mode, oldest join first
+    fn visible_sessions(&self) -> Vec<&Session> {
+        match (self.mode, &self.pinned) {
+            (SessionMode::Pinned, Some(pinned)) => self.sessions.get(pinned).into_iter().collect(),
+            // Pinned without pinned_session falls back to the last joined session
+            (SessionMode::LastJoined | SessionMode::Pinned, _) => self
+                .sessions
+                .values()
+                .filter(|s| s.channel_id.is_some())
+                .max_by_key(|s| s.joined_at)
+                .into_iter()
+                .collect(),
+            (SessionMode::Merged, _) => {
+                let mut sessions: Vec<&Session> = self.sessions.values().collect();
+                sessions.sort_by_key(|s| s.joined_at);
+                sessions
+            }
+        }
+    }
+
+    /// Session voice controls go to: the pinned one, otherwise the last one to join a channel.
+    /// `None` when that session's plugin is not connected.
+    pub fn control_target(&self) -> Option<&str> {
+        let online = |s: &Session| s.lost_at.is_none();
+        if let (SessionMode::Pinned, Some(pinned)) = (self.mode, &self.pinned) {
+            return self.sessions.get(pinned).filter(|s| online(s)).map(|_| pinned.as_str());
+        }
+        self.sessions
+            .iter()
+            .filter(|(_, s)| s.channel_id.is_some() && online(s))
+            .max_by_key(|(_, s)| s.joined_at)
+            .map(|(id, _)| id.as_str())
+    }
+
+    /// Users the overlay should show, according to the session mode
+    pub fn visible_users(&self) -> Vec<VoiceUser> {
+        // The same user seen by two clients is shown once
+        let mut users: Vec<VoiceUser> = Vec::new();
+        for user in self.visible_sessions().into_iter().flat_map(|s| s.users.iter()) {
+            if !users.iter().any(|u| u.user_id == user.user_id) {
+                users.push(user.clone());
+            }
+        }
+        users
+    }
+
+    /// Layout for the visible roster: stage as soon as one shown channel is a stage
+    pub fn visible_channel_type(&self) -> ChannelType {
+        if self
+            .visible_sessions()
+            .iter()
+            .any(|s| s.channel_type == ChannelType::Stage)
+        {
+            ChannelType::Stage
+        } else {
+            ChannelType::Voice
+        }
+    }
+
+    /// Whether `channel_id` is the voice channel of a shown session
+    pub fn shows_channel(&self, channel_id: &str) -> bool {
+        self.visible_sessions()
+            .iter()
+            .any(|s| s.channel_id.as_deref() == Some(channel_id))
+    }
+
+    /// Whether part of what the overlay shows comes from a plugin that is gone
+    pub fn visible_source_lost(&self) -> bool {
+        self.visible_sessions().iter().any(|s| s.lost_at.is_some())
+    }
+
+    /// The shown roster and its channels
+    pub fn roster_report(&self) -> RosterReport {
+        let channels = self
+            .visible_sessions()
+            .into_iter()
+            .filter_map(|s| {
+                Some(ChannelReport {
+                    channel_id: s.channel_id.clone()?,
+                    channel_name: s.channel_name.clone(),
+                })
+            })
+            .collect();
+
+        RosterReport {
+            channels,
+            channel_type: self.visible_channel_type(),
+            source_lost: self.visible_source_lost(),
+            users: self.visible_users(),
+        }
+    }
+
+    /// Every known session, ordered by id
+    pub fn session_reports(&self) -> Vec<SessionReport> {
+        let visible = self.visible_sessions();
+        let mut reports: Vec<SessionReport> = self
+            .sessions
+            .iter()
+            .map(|(id, s)| SessionReport {
+                session_id: id.clone(),
+                channel_id: s.channel_id.clone(),
+                channel_name: s.channel_name.clone(),
+                channel_type: s.channel_type,
+                user_count: s.users.len(),
+                visible: visible.iter().any(|v| std::ptr::eq(*v, s)),
+                source_lost: s.lost_at.is_some(),
+            })
+            .collect();
+        reports.sort_by(|a, b| a.session_id.cmp(&b.session_id));
+        reports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(user_id: &str, channel_id: &str) -> VoiceUser {
+        VoiceUser {
+            user_id: user_id.to_string(),
+            username: user_id.to_string(),
+            channel_id: Some(channel_id.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn join(manager: &mut SessionManager, session_id: &str, channel_id: &str) {
+        let users = vec![user(session_id, channel_id)];
+        manager.on_channel_joined(session_id, users, channel_id.to_string(), ChannelType::Voice);
+    }
+
+    fn session_ids(manager: &SessionManager) -> Vec<String> {
+        manager.session_reports().into_iter().map(|r| r.session_id).collect()
+    }
+
+    #[test]
+    fn rename_moves_placeholder_session() {
+        let mut manager = SessionManager::new(SessionMode::Merged, None);
+        join(&mut manager, "connection-1", "10");
+        manager.on_session_renamed("connection-1", "alice");
+
+        assert_eq!(session_ids(&manager), ["alice"]);
+        assert_eq!(manager.visible_users().len(), 1);
+    }
+
+    #[test]
+    fn rename_keeps_known_session_over_empty_placeholder() {
+        let mut manager = SessionManager::new(SessionMode::Merged, None);
+        join(&mut manager, "alice", "10");
+        manager.on_source_lost("alice");
+        manager.on_snapshot_requested("connection-2");
+        manager.on_session_renamed("connection-2", "alice");
+
+        assert_eq!(session_ids(&manager), ["alice"]);
+        assert_eq!(manager.visible_users().len(), 1);
+    }
+
+    #[test]
+    fn closed_session_is_dropped() {
+        let mut manager = SessionManager::new(SessionMode::Merged, None);
+        join(&mut manager, "alice", "10");
+        join(&mut manager, "bob", "20");
+        manager.on_session_closed("alice");
+
+        assert_eq!(session_ids(&manager), ["bob"]);
+    }
+
+    fn full_update(user_id: &str, channel_id: &str, username: Option<&str>) -> VoiceUserPartial {
+        VoiceUserPartial {
+            user_id: user_id.to_string(),
+            username: username.map(str::to_string),
+            channel_id: Field::Value(channel_id.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unknown_users_need_a_name() {
+        let mut manager = SessionManager::new(SessionMode::LastJoined, None);
+        join(&mut manager, "alice", "10");
+        manager.on_voice_state_update("alice", full_update("bob", "10", None));
+        manager.on_voice_state_update("alice", full_update("carol", "10", Some("carol")));
+
+        let names: Vec<String> = manager.visible_users().into_iter().map(|u| u.username).collect();
+        assert_eq!(names, ["alice", "carol"]);
+    }
+
+    #[test]
+    fn channel_joined_ends_snapshot_wait() {
+        let mut manager = SessionManager::new(SessionMode::LastJoined, None);
+        manager.on_snapshot_requested("alice");
+        manager.on_voice_state_update("alice", full_update("bob", "10", Some("bob")));
+        assert!(manager.visible_users().is_empty());
+
+        join(&mut manager, "alice", "10");
+        manager.on_voice_state_update("alice", full_update("bob", "10", Some("bob")));
+        assert_eq!(manager.visible_users().len(), 2);
+    }
+
+    #[test]
+    fn pinned_without_session_shows_last_joined() {
+        let mut manager = SessionManager::new(SessionMode::Pinned, None);
+        join(&mut manager, "alice", "10");
+        join(&mut manager, "bob", "20");
+        assert!(manager.shows_channel("20"));
+        assert!(!manager.shows_channel("10"));
+
+        manager.set_mode(SessionMode::Pinned, Some("alice".to_string()));
+        assert!(manager.shows_channel("10"));
+        assert!(!manager.shows_channel("20"));
+    }
+
+    #[test]
+    fn controls_go_to_one_session() {
+        let mut manager = SessionManager::new(SessionMode::Merged, None);
+        assert_eq!(manager.control_target(), None);
+
+        join(&mut manager, "alice", "10");
+        join(&mut manager, "bob", "20");
+        assert_eq!(manager.control_target(), Some("bob"));
+
+        manager.on_source_lost("bob");
+        assert_eq!(manager.control_target(), Some("alice"));
+
+        manager.set_mode(SessionMode::Pinned, Some("bob".to_string()));
+        assert_eq!(manager.control_target(), None);
+    }
+}
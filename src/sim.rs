@@ -0,0 +1,748 @@
+// The sim only reads the daemon's config, it never writes it back
+#[allow(dead_code)]
+mod config;
+// Only the protocol constants and the HELLO message are used here
+#[allow(dead_code)]
+mod discord_data;
+mod pairing;
+
+use config::Config;
+use discord_data::{HelloMessage, OutgoingMessage, DAEMON_CAPABILITIES, PROTOCOL_VERSION};
+use futures_util::{SinkExt, StreamExt};
+use pairing::PairingToken;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tracing::{info, warn, Level};
+use tracing_subscriber::FmtSubscriber;
+
+type SimResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+const USAGE: &str = "\
+Usage: chotop-sim [OPTIONS] <SCENARIO>
+       chotop-sim [OPTIONS] --stress <USERS>
+
+Plays a scripted scenario (.toml or .json) against the daemon, like the OrbolayBridge plugin would.
+
+Options:
+  --url <URL>          Daemon WebSocket URL (default: ws://127.0.0.1:<port from config>)
+  --socket <PATH>      Connect to the daemon's Unix socket instead
+  --token <TOKEN>      Pairing token (default: read from the daemon's token file)
+  --speed <FACTOR>     Play the scenario FACTOR times faster (default 1)
+  --stress <USERS>     Instead of a scenario, put USERS users in a channel and make them talk
+  --duration <SECS>    Length of the --stress run (default 60)
+  --rate <BURSTS>      Speaking bursts per second during --stress (default 20)
+  -h, --help           Print this help";
+
+/// A scripted sequence of voice events
+#[derive(Debug, Deserialize)]
+struct Scenario {
+    /// Discord user id the simulated client is logged in as
+    #[serde(default = "default_self_id")]
+    self_id: String,
+    /// Start over once the last step has played
+    #[serde(default)]
+    repeat: bool,
+    #[serde(default)]
+    users: Vec<SimUser>,
+    #[serde(default)]
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct SimUser {
+    id: String,
+    name: String,
+    /// Avatar hash or URL
+    #[serde(default)]
+    avatar: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Step {
+    /// Seconds from the start of the scenario
+    at: f64,
+    #[serde(flatten)]
+    action: Action,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum Action {
+    /// Join (or switch to) a voice channel with these users already in it
+    #[serde(alias = "switch_channel")]
+    JoinChannel {
+        channel: String,
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        users: Vec<String>,
+    },
+    LeaveChannel,
+    Join {
+        user: String,
+    },
+    Leave {
+        user: String,
+    },
+    Speak {
+        user: String,
+        /// Seconds
+        #[serde(default = "default_burst")]
+        duration: f64,
+    },
+    /// Set mute/deaf/stream, or toggle it when `value` is omitted
+    Mute {
+        user: String,
+        #[serde(default)]
+        value: Option<bool>,
+    },
+    Deafen {
+        user: String,
+        #[serde(default)]
+        value: Option<bool>,
+    },
+    Stream {
+        user: String,
+        #[serde(default)]
+        value: Option<bool>,
+    },
+    /// Random speaking bursts from whoever is in the channel
+    RandomSpeaking {
+        /// Seconds
+        duration: f64,
+        /// Bursts per second
+        #[serde(default = "default_rate")]
+        rate: f64,
+    },
+    /// Message in the current voice channel's text chat
+    Chat {
+        user: String,
+        content: String,
+    },
+    Notify {
+        title: String,
+        #[serde(default)]
+        body: String,
+        #[serde(default)]
+        channel: Option<String>,
+        #[serde(default)]
+        channel_name: Option<String>,
+        #[serde(default)]
+        guild: Option<String>,
+        #[serde(default)]
+        mentioned: bool,
+        #[serde(default)]
+        attachments: Vec<String>,
+    },
+}
+
+fn default_self_id() -> String {
+    "100000000000000000".to_string()
+}
+
+fn default_burst() -> f64 {
+    1.0
+}
+
+fn default_rate() -> f64 {
+    2.0
+}
+
+impl Scenario {
+    fn load(path: &Path) -> SimResult<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let scenario = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&content)?,
+            _ => toml::from_str(&content)?,
+        };
+        Ok(scenario)
+    }
+
+    /// Everyone joins one channel, then talks at random
+    fn stress(users: usize, duration: f64, rate: f64) -> Self {
+        let users: Vec<SimUser> = (1..=users)
+            .map(|n| SimUser {
+                id: format!("{}", 200000000000000000u64 + n as u64),
+                name: format!("User {}", n),
+                avatar: None,
+            })
+            .collect();
+
+        let steps = vec![
+            Step {
+                at: 0.0,
+                action: Action::JoinChannel {
+                    channel: "stress".to_string(),
+                    name: Some("Stress Test".to_string()),
+                    users: users.iter().map(|u| u.id.clone()).collect(),
+                },
+            },
+            Step {
+                at: 0.5,
+                action: Action::RandomSpeaking { duration, rate },
+            },
+            Step {
+                at: duration + 3.0,
+                action: Action::LeaveChannel,
+            },
+        ];
+
+        Self {
+            self_id: default_self_id(),
+            repeat: false,
+            users,
+            steps,
+        }
+    }
+}
+
+/// What the timeline runs
+#[derive(Debug)]
+enum Op {
+    Action(Action),
+    StopSpeaking(String),
+    RandomBurst { until: Duration, rate: f64 },
+}
+
+struct Scheduled {
+    at: Duration,
+    seq: u64,
+    op: Op,
+}
+
+// Earliest first in a max-heap, ties in insertion order
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.at.cmp(&self.at).then(other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scheduled {}
+
+/// Voice state of someone in the simulated channel
+#[derive(Debug, Clone, Default)]
+struct Member {
+    id: String,
+    mute: bool,
+    deaf: bool,
+    streaming: bool,
+    speaking: bool,
+}
+
+/// Small xorshift generator, good enough for speaking patterns
+struct Rng(u64);
+
+impl Rng {
+    fn from_time() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0x2545f4914f6cdd1d);
+        Self(seed | 1)
+    }
+
+    /// Uniform in [0, 1)
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// The simulated Discord client
+struct Simulator {
+    scenario: Scenario,
+    directory: HashMap<String, SimUser>,
+    speed: f64,
+    timeline: BinaryHeap<Scheduled>,
+    next_seq: u64,
+    channel: Option<(String, String)>,
+    members: Vec<Member>,
+    rng: Rng,
+}
+
+impl Simulator {
+    fn new(scenario: Scenario, speed: f64) -> Self {
+        let directory = scenario
+            .users
+            .iter()
+            .map(|u| (u.id.clone(), u.clone()))
+            .collect();
+
+        let mut sim = Self {
+            scenario,
+            directory,
+            speed,
+            timeline: BinaryHeap::new(),
+            next_seq: 0,
+            channel: None,
+            members: Vec::new(),
+            rng: Rng::from_time(),
+        };
+        sim.schedule_steps(Duration::ZERO);
+        sim
+    }
+
+    fn scaled(&self, seconds: f64) -> Duration {
+        Duration::from_secs_f64((seconds / self.speed).max(0.0))
+    }
+
+    fn schedule(&mut self, at: Duration, op: Op) {
+        self.next_seq += 1;
+        self.timeline.push(Scheduled {
+            at,
+            seq: self.next_seq,
+            op,
+        });
+    }
+
+    fn schedule_steps(&mut self, base: Duration) {
+        for step in self.scenario.steps.clone() {
+            let at = base + self.scaled(step.at);
+            self.schedule(at, Op::Action(step.action));
+        }
+    }
+
+    fn member_mut(&mut self, user_id: &str) -> Option<&mut Member> {
+        self.members.iter_mut().find(|m| m.id == user_id)
+    }
+
+    fn voice_user(&self, member: &Member) -> Value {
+        let user = self.directory.get(&member.id);
+        let name = match user {
+            Some(user) => user.name.clone(),
+            None if member.id == self.scenario.self_id => "You".to_string(),
+            None => format!("User {}", member.id),
+        };
+
+        json!({
+            "userId": member.id,
+            "username": name,
+            "avatarUrl": user.and_then(|u| u.avatar.clone()),
+            "channelId": self.channel.as_ref().map(|(id, _)| id.clone()),
+            "deaf": member.deaf,
+            "mute": member.mute,
+            "streaming": member.streaming,
+            "speaking": member.speaking,
+        })
+    }
+
+    fn state_update(&self, user_id: &str) -> Vec<Value> {
+        self.members
+            .iter()
+            .find(|m| m.id == user_id)
+            .map(|m| vec![json!({ "cmd": "VOICE_STATE_UPDATE", "state": self.voice_user(m) })])
+            .unwrap_or_default()
+    }
+
+    fn speaking_update(user_id: &str, speaking: bool) -> Value {
+        json!({ "cmd": "VOICE_STATE_UPDATE", "state": { "userId": user_id, "speaking": speaking } })
+    }
+
+    fn snapshot(&self) -> Value {
+        json!({
+            "cmd": "VOICE_SNAPSHOT",
+            "channelId": self.channel.as_ref().map(|(id, _)| id.clone()),
+            "channelName": self.channel.as_ref().map(|(_, name)| name.clone()),
+            "states": self.members.iter().map(|m| self.voice_user(m)).collect::<Vec<_>>(),
+        })
+    }
+
+    /// Apply a timeline entry, returning the frames to send
+    fn run(&mut self, now: Duration, op: Op) -> Vec<Value> {
+        match op {
+            Op::Action(action) => self.apply(now, action),
+            Op::StopSpeaking(user_id) => match self.member_mut(&user_id) {
+                Some(member) if member.speaking => {
+                    member.speaking = false;
+                    vec![Self::speaking_update(&user_id, false)]
+                }
+                _ => Vec::new(),
+            },
+            Op::RandomBurst { until, rate } => {
+                if now >= until {
+                    return Vec::new();
+                }
+
+                // Exponential gaps give a natural, bursty conversation
+                let gap = -(1.0 - self.rng.next()).ln() / rate;
+                let next = now + self.scaled(gap);
+                self.schedule(next, Op::RandomBurst { until, rate });
+
+                let quiet: Vec<String> = self
+                    .members
+                    .iter()
+                    .filter(|m| !m.speaking && !m.mute && !m.deaf)
+                    .map(|m| m.id.clone())
+                    .collect();
+                if quiet.is_empty() {
+                    return Vec::new();
+                }
+                let user_id = quiet[(self.rng.next() * quiet.len() as f64) as usize].clone();
+                let length = 0.3 + self.rng.next() * 2.2;
+                self.apply(now, Action::Speak { user: user_id, duration: length })
+            }
+        }
+    }
+
+    fn apply(&mut self, now: Duration, action: Action) -> Vec<Value> {
+        match action {
+            Action::JoinChannel { channel, name, users } => {
+                let name = name.unwrap_or_else(|| channel.clone());
+                info!("Joining channel {} with {} users", name, users.len());
+                self.channel = Some((channel, name.clone()));
+
+                // The local user is always part of the channel they join
+                self.members = std::iter::once(self.scenario.self_id.clone())
+                    .chain(users.into_iter().filter(|id| *id != self.scenario.self_id))
+                    .map(|id| Member { id, ..Default::default() })
+                    .collect();
+
+                let states: Vec<Value> = self.members.iter().map(|m| self.voice_user(m)).collect();
+                vec![json!({ "cmd": "CHANNEL_JOINED", "channelName": name, "states": states })]
+            }
+            Action::LeaveChannel => {
+                info!("Leaving channel");
+                self.channel = None;
+                self.members.clear();
+                vec![json!({ "cmd": "CHANNEL_LEFT" })]
+            }
+            Action::Join { user } => {
+                if self.channel.is_none() || self.member_mut(&user).is_some() {
+                    return Vec::new();
+                }
+                self.members.push(Member { id: user.clone(), ..Default::default() });
+                self.state_update(&user)
+            }
+            Action::Leave { user } => {
+                if self.member_mut(&user).is_none() {
+                    return Vec::new();
+                }
+                self.members.retain(|m| m.id != user);
+                vec![json!({ "cmd": "VOICE_STATE_UPDATE", "state": { "userId": user, "channelId": null } })]
+            }
+            Action::Speak { user, duration } => {
+                let stop_at = now + self.scaled(duration);
+                let Some(member) = self.member_mut(&user) else {
+                    return Vec::new();
+                };
+                let started = !member.speaking;
+                member.speaking = true;
+                self.schedule(stop_at, Op::StopSpeaking(user.clone()));
+                if started {
+                    vec![Self::speaking_update(&user, true)]
+                } else {
+                    Vec::new()
+                }
+            }
+            Action::Mute { user, value } => {
+                let Some(member) = self.member_mut(&user) else {
+                    return Vec::new();
+                };
+                member.mute = value.unwrap_or(!member.mute);
+                self.state_update(&user)
+            }
+            Action::Deafen { user, value } => {
+                let Some(member) = self.member_mut(&user) else {
+                    return Vec::new();
+                };
+                member.deaf = value.unwrap_or(!member.deaf);
+                self.state_update(&user)
+            }
+            Action::Stream { user, value } => {
+                let Some(member) = self.member_mut(&user) else {
+                    return Vec::new();
+                };
+                member.streaming = value.unwrap_or(!member.streaming);
+                self.state_update(&user)
+            }
+            Action::RandomSpeaking { duration, rate } => {
+                let until = now + self.scaled(duration);
+                self.schedule(now, Op::RandomBurst { until, rate: rate.max(0.01) });
+                Vec::new()
+            }
+            Action::Chat { user, content } => {
+                let Some((channel_id, _)) = &self.channel else {
+                    return Vec::new();
+                };
+                let author = self.directory.get(&user).map_or(user.clone(), |u| u.name.clone());
+                vec![json!({
+                    "cmd": "CHANNEL_MESSAGE",
+                    "message": { "channelId": channel_id, "authorName": author, "content": content },
+                })]
+            }
+            Action::Notify { title, body, channel, channel_name, guild, mentioned, attachments } => {
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_millis() as u64)
+                    .unwrap_or_default();
+                let attachments: Vec<Value> = attachments
+                    .into_iter()
+                    .map(|filename| json!({ "filename": filename }))
+                    .collect();
+                vec![json!({
+                    "cmd": "MESSAGE_NOTIFICATION",
+                    "message": {
+                        "title": title,
+                        "body": body,
+                        "icon": null,
+                        "channelId": channel,
+                        "authorName": title,
+                        "channelName": channel_name,
+                        "guildName": guild,
+                        "isDm": channel_name.is_none() && guild.is_none(),
+                        "timestamp": timestamp,
+                        "mentioned": mentioned,
+                        "attachments": attachments,
+                    },
+                })]
+            }
+        }
+    }
+
+    /// Name the scenario gives `channel_id` in its join steps
+    fn channel_name(&self, channel_id: &str) -> Option<String> {
+        self.scenario.steps.iter().find_map(|step| match &step.action {
+            Action::JoinChannel { channel, name, .. } if channel == channel_id => name.clone(),
+            _ => None,
+        })
+    }
+
+    /// React to a command from the daemon, returning the frames to send back
+    fn on_daemon_message(&mut self, text: &str) -> Vec<Value> {
+        let Ok(message) = serde_json::from_str::<Value>(text) else {
+            warn!("Daemon sent invalid JSON: {}", text);
+            return Vec::new();
+        };
+
+        let self_id = self.scenario.self_id.clone();
+        match message["cmd"].as_str().unwrap_or_default() {
+            "HELLO" => {
+                info!("Daemon speaks protocol v{}", message["protocolVersion"]);
+                Vec::new()
+            }
+            "REQUEST_SNAPSHOT" => {
+                info!("Daemon requested a snapshot");
+                vec![self.snapshot()]
+            }
+            "TOGGLE_MUTE" => self.apply(Duration::ZERO, Action::Mute { user: self_id, value: None }),
+            "TOGGLE_DEAF" => self.apply(Duration::ZERO, Action::Deafen { user: self_id, value: None }),
+            "DISCONNECT" => self.apply(Duration::ZERO, Action::LeaveChannel),
+            "MOVE_TO_CHANNEL" => {
+                let Some(channel) = message["channelId"].as_str().map(str::to_string) else {
+                    warn!("Daemon sent MOVE_TO_CHANNEL without a channelId");
+                    return Vec::new();
+                };
+                // Nobody else is known to be in a channel the scenario never joined
+                let name = self.channel_name(&channel);
+                self.apply(Duration::ZERO, Action::JoinChannel { channel, name, users: Vec::new() })
+            }
+            other => {
+                info!("Daemon sent {}", other);
+                Vec::new()
+            }
+        }
+    }
+}
+
+struct Options {
+    url: Option<String>,
+    socket: Option<PathBuf>,
+    token: Option<String>,
+    speed: f64,
+    scenario: Option<PathBuf>,
+    stress: Option<usize>,
+    duration: f64,
+    rate: f64,
+}
+
+impl Options {
+    fn parse() -> Result<Option<Self>, String> {
+        let mut options = Self {
+            url: None,
+            socket: None,
+            token: None,
+            speed: 1.0,
+            scenario: None,
+            stress: None,
+            duration: 60.0,
+            rate: 20.0,
+        };
+        let mut args = std::env::args().skip(1);
+
+        fn number<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+            let value = value.ok_or_else(|| format!("{} needs a value", flag))?;
+            value.parse().map_err(|_| format!("invalid value for {}: {}", flag, value))
+        }
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--url" => options.url = Some(args.next().ok_or("--url needs a value")?),
+                "--socket" => options.socket = Some(args.next().ok_or("--socket needs a path")?.into()),
+                "--token" => options.token = Some(args.next().ok_or("--token needs a value")?),
+                "--speed" => options.speed = number("--speed", args.next())?,
+                "--stress" => options.stress = Some(number("--stress", args.next())?),
+                "--duration" => options.duration = number("--duration", args.next())?,
+                "--rate" => options.rate = number("--rate", args.next())?,
+                "-h" | "--help" => return Ok(None),
+                other if other.starts_with('-') => return Err(format!("unknown argument: {}", other)),
+                other => options.scenario = Some(PathBuf::from(other)),
+            }
+        }
+
+        if options.speed <= 0.0 || !options.speed.is_finite() {
+            return Err("--speed must be positive".to_string());
+        }
+        if options.scenario.is_none() && options.stress.is_none() {
+            return Err("give a scenario file or --stress <USERS>".to_string());
+        }
+        Ok(Some(options))
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let subscriber = FmtSubscriber::builder()
+        .with_max_level(Level::INFO)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber).expect("Failed to set subscriber");
+
+    let options = match Options::parse() {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let scenario = match (&options.scenario, options.stress) {
+        (_, Some(users)) => Scenario::stress(users, options.duration, options.rate),
+        (Some(path), None) => match Scenario::load(path) {
+            Ok(scenario) => scenario,
+            Err(e) => {
+                eprintln!("error: cannot load {:?}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        (None, None) => unreachable!("checked by Options::parse"),
+    };
+
+    if let Err(e) = connect_and_play(&options, scenario).await {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn connect_and_play(options: &Options, scenario: Scenario) -> SimResult<()> {
+    let config = Config::try_load().unwrap_or_else(|e| {
+        warn!("{}, using the default port and pairing settings", e);
+        Config::default()
+    });
+    let token = match &options.token {
+        Some(token) => Some(token.clone()),
+        None => config.require_pairing.then(PairingToken::load_or_create).transpose()?,
+    };
+    let sim = Simulator::new(scenario, options.speed);
+
+    if let Some(path) = &options.socket {
+        info!("Connecting to {:?}", path);
+        let stream = tokio::net::UnixStream::connect(path).await?;
+        let (ws, _) = tokio_tungstenite::client_async("ws://localhost/", stream).await?;
+        play(ws, sim, token).await
+    } else {
+        let url = options
+            .url
+            .clone()
+            .unwrap_or_else(|| format!("ws://127.0.0.1:{}", config.active_port()));
+        info!("Connecting to {}", url);
+        let (ws, _) = tokio_tungstenite::connect_async(url.as_str()).await?;
+        play(ws, sim, token).await
+    }
+}
+
+async fn play<S>(mut ws: WebSocketStream<S>, mut sim: Simulator, token: Option<String>) -> SimResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Same opening as the plugin: HELLO (with the pairing token), then REGISTER_CONFIG
+    // The sim plays every feature the daemon knows about
+    let hello = OutgoingMessage::Hello(HelloMessage {
+        protocol_version: PROTOCOL_VERSION,
+        capabilities: DAEMON_CAPABILITIES.to_vec(),
+        token,
+    });
+    ws.send(Message::Text(serde_json::to_string(&hello)?)).await?;
+    let register = json!({ "cmd": "REGISTER_CONFIG", "userId": sim.scenario.self_id });
+    ws.send(Message::Text(register.to_string())).await?;
+
+    let start = Instant::now();
+    let mut sent: u64 = 0;
+
+    loop {
+        if sim.timeline.is_empty() {
+            if !sim.scenario.repeat {
+                break;
+            }
+            info!("Scenario finished, starting over");
+            sim.schedule_steps(start.elapsed());
+            if sim.timeline.is_empty() {
+                break;
+            }
+        }
+        let next_at = sim.timeline.peek().map(|s| s.at).unwrap_or_default();
+
+        let frames = tokio::select! {
+            message = ws.next() => match message {
+                Some(Ok(Message::Text(text))) => sim.on_daemon_message(&text),
+                Some(Ok(Message::Close(frame))) => {
+                    let reason = frame.map(|f| f.reason.to_string()).unwrap_or_default();
+                    info!("Daemon closed the connection: {}", reason);
+                    return Ok(());
+                }
+                Some(Err(e)) => return Err(e.into()),
+                None => return Ok(()),
+                _ => Vec::new(),
+            },
+            _ = tokio::time::sleep_until(start + next_at) => {
+                match sim.timeline.pop() {
+                    Some(scheduled) => sim.run(scheduled.at, scheduled.op),
+                    None => Vec::new(),
+                }
+            }
+        };
+
+        for frame in frames {
+            ws.send(Message::Text(frame.to_string())).await?;
+            sent += 1;
+        }
+    }
+
+    info!("Scenario done, {} frames sent", sent);
+    ws.close(None).await?;
+    Ok(())
+}